@@ -1,11 +1,23 @@
 //! regular expression engine
 
 use crate::helper::DynError;
-use std::fmt::Display;
+use class::CharClass;
+use parser::Flags;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    ops::{Index, Range},
+    sync::Arc,
+};
 
+mod class;
 mod codegen;
 mod evaluator;
 mod parser;
+mod unicode_tables;
+
+pub use codegen::CodeGenError;
+pub use parser::ParseError;
 
 #[derive(Debug)]
 pub enum Instruction {
@@ -13,6 +25,66 @@ pub enum Instruction {
     Match,
     Jump(usize),
     Split(usize, usize),
+    Save(usize),
+    Class(CharClass),
+    Any(bool), // any char, including '\n' if true
+    Assert(Assertion),
+    Look {
+        look: Look,
+        min: usize,  // shortest length of the sub-program, used by lookbehind
+        max: usize,  // longest length of the sub-program, used by lookbehind
+        next: usize, // address just after the sub-program
+    },
+    LookEnd,              // end of the sub-program of Look
+    Backref(usize, bool), // text of the capture group, compared case insensitively if true
+    Atomic(usize), // run the sub-program just after it without backtracking into it, then jump
+    AtomicEnd,     // end of the sub-program of Atomic
+}
+
+/// kind of lookaround assertion, which matches a sub-expression without consuming chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Look {
+    Ahead,     // (?=...)
+    NegAhead,  // (?!...)
+    Behind,    // (?<=...)
+    NegBehind, // (?<!...)
+}
+
+impl Display for Look {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Look::Ahead => write!(f, "ahead"),
+            Look::NegAhead => write!(f, "negative ahead"),
+            Look::Behind => write!(f, "behind"),
+            Look::NegBehind => write!(f, "negative behind"),
+        }
+    }
+}
+
+/// zero-width assertion on the position in the input string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assertion {
+    StartText,             // \A, or ^ without multi line mode
+    EndText,               // \z, or $ without multi line mode
+    StartLine,             // ^ in multi line mode
+    EndLine,               // $ in multi line mode
+    WordBoundary(bool),    // \b, with Unicode word chars if true
+    NotWordBoundary(bool), // \B, with Unicode word chars if true
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Assertion::StartText => write!(f, "start of text"),
+            Assertion::EndText => write!(f, "end of text"),
+            Assertion::StartLine => write!(f, "start of line"),
+            Assertion::EndLine => write!(f, "end of line"),
+            Assertion::WordBoundary(true) => write!(f, "word boundary"),
+            Assertion::WordBoundary(false) => write!(f, "ascii word boundary"),
+            Assertion::NotWordBoundary(true) => write!(f, "not word boundary"),
+            Assertion::NotWordBoundary(false) => write!(f, "not ascii word boundary"),
+        }
+    }
 }
 
 impl Display for Instruction {
@@ -22,10 +94,50 @@ impl Display for Instruction {
             Instruction::Match => write!(f, "match"),
             Instruction::Jump(addr) => write!(f, "jump {:>04}", addr),
             Instruction::Split(addr1, addr2) => write!(f, "split {:>04}, {:>04}", addr1, addr2),
+            Instruction::Save(slot) => write!(f, "save {}", slot),
+            Instruction::Class(class) => write!(f, "class {}", class),
+            Instruction::Any(true) => write!(f, "any"),
+            Instruction::Any(false) => write!(f, "any except \\n"),
+            Instruction::Assert(assertion) => write!(f, "assert {}", assertion),
+            Instruction::Look {
+                look: look @ (Look::Behind | Look::NegBehind),
+                min,
+                max,
+                next,
+            } => write!(f, "look {look} {min}..={max} chars, next {:>04}", next),
+            Instruction::Look { look, next, .. } => write!(f, "look {look}, next {:>04}", next),
+            Instruction::LookEnd => write!(f, "look end"),
+            Instruction::Atomic(next) => write!(f, "atomic, next {:>04}", next),
+            Instruction::AtomicEnd => write!(f, "atomic end"),
+            Instruction::Backref(group, false) => write!(f, "backref {group}"),
+            Instruction::Backref(group, true) => write!(f, "backref {group} (case insensitive)"),
         }
     }
 }
 
+/// where a match may start and end in the input string.
+///
+/// # example usage
+///
+/// ```
+/// use myregex::{MatchKind, RegexBuilder};
+/// let search = RegexBuilder::new("abc?").build().unwrap();
+/// let prefix = RegexBuilder::new("abc?").match_kind(MatchKind::Prefix).build().unwrap();
+/// let full = RegexBuilder::new("abc?").match_kind(MatchKind::Full).build().unwrap();
+///
+/// assert!(search.is_match("xxabxyz").unwrap());
+/// assert!(!prefix.is_match("xxabxyz").unwrap());
+/// assert!(prefix.is_match("abxyz").unwrap());
+/// assert!(!full.is_match("abxyz").unwrap());
+/// assert!(full.is_match("ab").unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    Prefix, // match must start at the beginning of the input
+    Full,   // match must start at the beginning and end at the end of the input
+    Search, // match may start anywhere in the input
+}
+
 /// parses regular expressions and generates code,
 /// display AST and instruction sequence on standard output.
 ///
@@ -41,12 +153,12 @@ impl Display for Instruction {
 /// returns Err if there is an error in the input regular expression or an internal implementation error.
 pub fn print(expr: &str) -> Result<(), DynError> {
     println!("expr: {expr}");
-    let ast = parser::parse(expr)?;
-    println!("AST: {:?}", ast);
+    let parsed = parser::parse(expr, Flags::default())?;
+    println!("AST: {:?}", parsed.ast);
 
     println!();
     println!("code!");
-    let code = codegen::get_code(&ast)?;
+    let code = codegen::get_code(&parsed.ast, codegen::DEFAULT_SIZE_LIMIT, true)?;
     for (n, i) in code.iter().enumerate() {
         println!("{:>04}: {i}", n);
     }
@@ -54,13 +166,385 @@ pub fn print(expr: &str) -> Result<(), DynError> {
     Ok(())
 }
 
-/// match a regular expression with a string.
+/// compiled regular expression.
+///
+/// the expression is parsed and converted into an instruction sequence only once, in `Regex::new`,
+/// so the same `Regex` can be matched against any number of strings without recompiling.
+///
+/// # example usage
+///
+/// ```
+/// use myregex::Regex;
+/// let re = Regex::new("abc|(de|cd)+").unwrap();
+/// assert!(re.is_match("decddede").unwrap());
+/// assert!(!re.is_match("xyz").unwrap());
+/// ```
+#[derive(Debug)]
+pub struct Regex {
+    code: Vec<Instruction>,
+    use_dfs: bool,
+    kind: MatchKind,
+    names: Vec<Option<String>>,         // name of each capture group
+    index: Arc<HashMap<String, usize>>, // index of each named capture group
+}
+
+impl Regex {
+    /// parses regular expressions and generates code with the default settings
+    /// (depth-first search, `MatchKind::Search`).
+    ///
+    /// returns Err if there is an error in the input regular expression or an internal implementation error.
+    pub fn new(expr: &str) -> Result<Regex, DynError> {
+        RegexBuilder::new(expr).build()
+    }
+
+    /// match the compiled regular expression with line.
+    /// where the match may start and end is decided by the `MatchKind` given to `RegexBuilder`;
+    /// by default the match may start at any position of line.
+    ///
+    /// returns Ok(true) if executed without error and matching is **successful**,
+    /// returns Ok(false) if executed without error and matching **fails**.
+    ///
+    /// returns Err if an internal implementation error occurs.
+    pub fn is_match(&self, line: &str) -> Result<bool, DynError> {
+        Ok(self.find(line)?.is_some())
+    }
+
+    /// find the leftmost match of the compiled regular expression in line.
+    ///
+    /// # example usage
+    ///
+    /// ```
+    /// use myregex::Regex;
+    /// let re = Regex::new("(de|cd)+").unwrap();
+    /// let m = re.find("ab¢cdde").unwrap().unwrap();
+    /// assert_eq!(m.as_str(), "cdde");
+    /// assert_eq!(m.range(), 4..8);
+    /// assert_eq!(m.char_range(), 3..7);
+    /// ```
+    ///
+    /// # return value
+    ///
+    /// returns Ok(Some(m)) if executed without error and matching is **successful**,
+    /// returns Ok(None) if executed without error and matching **fails**.
+    ///
+    /// returns Err if an internal implementation error occurs.
+    pub fn find<'t>(&self, line: &'t str) -> Result<Option<Match<'t>>, DynError> {
+        Ok(self.captures(line)?.and_then(|caps| caps.get(0)))
+    }
+
+    /// find the leftmost match of the compiled regular expression in line,
+    /// together with the text matched by each capture group.
+    ///
+    /// # example usage
+    ///
+    /// ```
+    /// use myregex::Regex;
+    /// let re = Regex::new("(a+)(?P<last>b|c)").unwrap();
+    /// let caps = re.captures("xxaacyy").unwrap().unwrap();
+    /// assert_eq!(&caps[0], "aac");
+    /// assert_eq!(&caps[1], "aa");
+    /// assert_eq!(&caps["last"], "c");
+    /// assert_eq!(caps.get(2).unwrap().range(), 4..5);
+    /// ```
+    ///
+    /// # return value
+    ///
+    /// returns Ok(Some(caps)) if executed without error and matching is **successful**,
+    /// returns Ok(None) if executed without error and matching **fails**.
+    ///
+    /// returns Err if an internal implementation error occurs.
+    pub fn captures<'t>(&self, line: &'t str) -> Result<Option<Captures<'t>>, DynError> {
+        let chars = line.chars().collect::<Vec<char>>();
+        let nslots = self.names.len() * 2;
+        let slots = evaluator::eval(&self.code, &chars, self.use_dfs, self.kind, nslots)?;
+        Ok(slots.map(|slots| Captures::new(line, &slots, self.index.clone())))
+    }
+
+    /// number of capture groups, including group 0 for the whole match.
+    pub fn captures_len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// names of the capture groups in order, None for a group without name.
+    ///
+    /// # example usage
+    ///
+    /// ```
+    /// use myregex::Regex;
+    /// let re = Regex::new("(?P<year>a+)-(b+)").unwrap();
+    /// let names = re.capture_names().collect::<Vec<_>>();
+    /// assert_eq!(names, vec![None, Some("year"), None]);
+    /// ```
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.names.iter().map(|name| name.as_deref())
+    }
+}
+
+/// text matched by each capture group.
+///
+/// group 0 is the whole match, and the other groups are numbered from 1 in order of their left parenthesis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'t> {
+    groups: Vec<Option<Match<'t>>>,
+    index: Arc<HashMap<String, usize>>, // index of each named capture group
+}
+
+impl<'t> Captures<'t> {
+    /// create captures of text from slots holding char offsets.
+    fn new(
+        text: &'t str,
+        slots: &[Option<usize>],
+        index: Arc<HashMap<String, usize>>,
+    ) -> Captures<'t> {
+        // byte offset of each char, and of the end of text
+        let offsets = text
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(text.len()))
+            .collect::<Vec<usize>>();
+
+        let groups = slots
+            .chunks(2)
+            .map(|slot| match slot {
+                [Some(start), Some(end)] => Some(Match {
+                    text,
+                    start: offsets[*start],
+                    end: offsets[*end],
+                    char_start: *start,
+                    char_end: *end,
+                }),
+                _ => None,
+            })
+            .collect();
+        Captures { groups, index }
+    }
+
+    /// match of capture group i.
+    /// returns None if group i does not exist or did not take part in the match.
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        self.groups.get(i).copied().flatten()
+    }
+
+    /// match of the capture group named name.
+    /// returns None if there is no such group or it did not take part in the match.
+    pub fn name(&self, name: &str) -> Option<Match<'t>> {
+        self.index.get(name).and_then(|i| self.get(*i))
+    }
+
+    /// number of capture groups, including group 0 for the whole match.
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// always false, since group 0 exists.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// iterate over the matches of all capture groups in order.
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'t>>> + '_ {
+        self.groups.iter().copied()
+    }
+}
+
+/// text matched by capture group i.
+///
+/// panics if group i does not exist or did not take part in the match.
+impl Index<usize> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        self.get(i)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no group at index {i}"))
+    }
+}
+
+/// text matched by the capture group named name.
+///
+/// panics if there is no such group or it did not take part in the match.
+impl Index<&str> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, name: &str) -> &str {
+        self.name(name)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no group named '{name}'"))
+    }
+}
+
+/// a match found in the input string.
+///
+/// the position of the match is available both as byte offsets (for slicing the input)
+/// and as char offsets (for counting characters).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+    char_start: usize,
+    char_end: usize,
+}
+
+impl<'t> Match<'t> {
+    /// byte offset of the start of the match.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// byte offset of the end of the match.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// byte range of the match.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// char offset of the start of the match.
+    pub fn char_start(&self) -> usize {
+        self.char_start
+    }
+
+    /// char offset of the end of the match.
+    pub fn char_end(&self) -> usize {
+        self.char_end
+    }
+
+    /// char range of the match.
+    pub fn char_range(&self) -> Range<usize> {
+        self.char_start..self.char_end
+    }
+
+    /// matched text.
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.range()]
+    }
+}
+
+/// builder to configure how a `Regex` is compiled.
+///
+/// # example usage
+///
+/// ```
+/// use myregex::RegexBuilder;
+/// let re = RegexBuilder::new("(ab|cd)+").use_dfs(false).build().unwrap();
+/// assert!(re.is_match("abcdcd").unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    expr: String,
+    use_dfs: bool,
+    kind: MatchKind,
+    flags: Flags,
+    size_limit: usize,
+}
+
+impl RegexBuilder {
+    /// creates a builder for expr with the default settings.
+    pub fn new(expr: &str) -> RegexBuilder {
+        RegexBuilder {
+            expr: expr.to_string(),
+            use_dfs: true,
+            kind: MatchKind::Search,
+            flags: Flags::default(),
+            size_limit: codegen::DEFAULT_SIZE_LIMIT,
+        }
+    }
+
+    /// if use_dfs is true, depth-first search is used; if false, width-first search is used.
+    ///
+    /// width-first search cannot run lookaround, backreferences, atomic groups and possessive quantifiers,
+    /// so `build` fails with `CodeGenError::Unsupported` for them.
+    pub fn use_dfs(&mut self, use_dfs: bool) -> &mut RegexBuilder {
+        self.use_dfs = use_dfs;
+        self
+    }
+
+    /// set where a match may start and end in the input string.
+    pub fn match_kind(&mut self, kind: MatchKind) -> &mut RegexBuilder {
+        self.kind = kind;
+        self
+    }
+
+    /// if yes is true (default), `\d`, `\w`, `\s` and `\b` use Unicode digits, word chars and white spaces;
+    /// if false, they match only ASCII chars.
+    pub fn unicode(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.unicode = yes;
+        self
+    }
+
+    /// if yes is true, letters match regardless of case, the same as `(?i)`; if false (default), case matters.
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// if yes is true, white spaces in the expression are ignored and `#` starts a comment until the end of line,
+    /// the same as `(?x)`; if false (default), they match themselves.
+    ///
+    /// white spaces and `#` can still be matched by escaping them, such as `\ ` and `\#`.
+    pub fn ignore_whitespace(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.ignore_whitespace = yes;
+        self
+    }
+
+    /// if yes is true, `^` and `$` match at the start and end of each line;
+    /// if false (default), they match only at the start and end of the input string.
+    /// `(?m)` sets it inside the expression.
+    pub fn multi_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// if yes is true, `.` matches any char including '\n'; if false (default), it matches any char except '\n'.
+    /// `(?s)` sets it inside the expression.
+    pub fn dot_matches_new_line(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.flags.dot_matches_new_line = yes;
+        self
+    }
+
+    /// set the upper bound on the number of instructions generated from the expression.
+    ///
+    /// repetitions such as `a{1000}` are expanded into copies of the repeated expression,
+    /// so `build` fails with `CodeGenError::TooLarge` for expressions like `(a{1000}){1000}`.
+    pub fn size_limit(&mut self, limit: usize) -> &mut RegexBuilder {
+        self.size_limit = limit;
+        self
+    }
+
+    /// parses regular expressions and generates code.
+    ///
+    /// returns Err if there is an error in the input regular expression or an internal implementation error.
+    pub fn build(&self) -> Result<Regex, DynError> {
+        let parsed = parser::parse(&self.expr, self.flags)?;
+        let code = codegen::get_code(&parsed.ast, self.size_limit, self.use_dfs)?;
+        let index = parsed
+            .names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| Some((name.clone()?, i)))
+            .collect();
+        Ok(Regex {
+            code,
+            use_dfs: self.use_dfs,
+            kind: self.kind,
+            names: parsed.names,
+            index: Arc::new(index),
+        })
+    }
+}
+
+/// search a string for a regular expression.
+/// the match may start at any position of the string.
 ///
 /// # example usage
 ///
 /// ```
 /// use myregex;
-/// myregex::do_matching("abc|(de|cd)+", "decddede", true);
+/// assert!(myregex::do_matching("abc|(de|cd)+", "decddede", true).unwrap());
+/// assert!(myregex::do_matching("abc|(de|cd)+", "xxabcxx", true).unwrap());
 /// ```
 ///
 /// # arguments
@@ -68,6 +552,8 @@ pub fn print(expr: &str) -> Result<(), DynError> {
 /// expr is the regular expression, line is the string to match.
 /// if is_depth is true, depth-first search is used; if false, width-first search is used.
 ///
+/// this compiles expr on every call; use `Regex` to match the same expression many times.
+///
 /// # return value
 ///
 /// returns Ok(true) if executed without error and matching is **successful**,
@@ -75,8 +561,57 @@ pub fn print(expr: &str) -> Result<(), DynError> {
 ///
 /// returns Err if there is an error in the input regular expression or an internal implementation error.
 pub fn do_matching(expr: &str, line: &str, use_dfs: bool) -> Result<bool, DynError> {
-    let ast = parser::parse(expr)?;
-    let code = codegen::get_code(&ast)?;
-    let line = line.chars().collect::<Vec<char>>();
-    Ok(evaluator::eval(&code, &line, use_dfs)?)
+    RegexBuilder::new(expr)
+        .use_dfs(use_dfs)
+        .build()?
+        .is_match(line)
+}
+
+/// escape all metacharacters and white spaces in text, so that the result matches text literally,
+/// even if white spaces are ignored by `(?x)`.
+///
+/// # example usage
+///
+/// ```
+/// use myregex::{escape, Regex};
+/// assert_eq!(escape("a|b.c"), "a\\|b\\.c");
+///
+/// let re = Regex::new(&format!("^{}$", escape("1+1=[2]"))).unwrap();
+/// assert!(re.is_match("1+1=[2]").unwrap());
+/// assert!(!re.is_match("11=2").unwrap());
+/// ```
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if parser::is_meta_char(c) || c.is_whitespace() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// find the leftmost match of a regular expression in a string.
+///
+/// # example usage
+///
+/// ```
+/// use myregex;
+/// let m = myregex::find("(de|cd)+", "abcdde", true).unwrap().unwrap();
+/// assert_eq!((m.start(), m.end()), (2, 6));
+/// ```
+///
+/// # arguments
+///
+/// expr is the regular expression, line is the string to search.
+/// if is_depth is true, depth-first search is used; if false, width-first search is used.
+///
+/// # return value
+///
+/// returns Ok(Some(m)) with the position of the match if executed without error and matching is **successful**,
+/// returns Ok(None) if executed without error and matching **fails**.
+///
+/// returns Err if there is an error in the input regular expression or an internal implementation error.
+pub fn find<'t>(expr: &str, line: &'t str, use_dfs: bool) -> Result<Option<Match<'t>>, DynError> {
+    RegexBuilder::new(expr).use_dfs(use_dfs).build()?.find(line)
 }
@@ -0,0 +1,292 @@
+//! set of chars used by character classes
+
+use super::unicode_tables::{
+    case_folding_simple, general_category, perl_decimal, perl_space, perl_word, property_values,
+    script,
+};
+use std::fmt::{self, Display};
+
+/// set of chars held as sorted ranges.
+///
+/// ranges never overlap nor touch each other, so two classes with the same chars are equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>, // inclusive ranges (lo, hi)
+}
+
+/// char just after c, skipping surrogates
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{d7ff}' => Some('\u{e000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+/// char just before c, skipping surrogates
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{e000}' => Some('\u{d7ff}'),
+        _ => char::from_u32((c as u32).checked_sub(1)?),
+    }
+}
+
+/// true if c is in one of the sorted ranges
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                std::cmp::Ordering::Less
+            } else if lo > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// table of the value of property, such as "Greek" of "Script", by its alias normalized by `CharClass::property`
+fn property_value(
+    property: &str,
+    alias: &str,
+    by_name: &[(&str, &'static [(char, char)])],
+) -> Option<&'static [(char, char)]> {
+    let (_, values) = property_values::PROPERTY_VALUES
+        .iter()
+        .find(|(p, _)| *p == property)?;
+    let i = values.binary_search_by_key(&alias, |&(a, _)| a).ok()?;
+    let j = by_name
+        .binary_search_by_key(&values[i].1, |&(name, _)| name)
+        .ok()?;
+    Some(by_name[j].1)
+}
+
+/// true if c is a word char of `\w`
+pub fn is_word_char(c: char, unicode: bool) -> bool {
+    if unicode {
+        in_ranges(perl_word::PERL_WORD, c)
+    } else {
+        c.is_ascii_alphanumeric() || c == '_'
+    }
+}
+
+/// true if a and b are the same char, or the same char except case by Unicode simple case folding
+pub fn equal_fold(a: char, b: char) -> bool {
+    let table = case_folding_simple::CASE_FOLDING_SIMPLE;
+    a == b
+        || table
+            .binary_search_by_key(&a, |&(c, _)| c)
+            .is_ok_and(|i| table[i].1.contains(&b))
+}
+
+impl CharClass {
+    /// empty class
+    pub fn new() -> Self {
+        CharClass::default()
+    }
+
+    /// class of the given ranges, which may be unsorted and overlap
+    pub fn from_ranges(ranges: &[(char, char)]) -> Self {
+        let mut class = CharClass {
+            ranges: ranges.to_vec(),
+        };
+        class.canonicalize();
+        class
+    }
+
+    /// `\d`: decimal digits, only 0-9 if unicode is false
+    pub fn digit(unicode: bool) -> Self {
+        if unicode {
+            CharClass::from_ranges(perl_decimal::DECIMAL_NUMBER)
+        } else {
+            CharClass::from_ranges(&[('0', '9')])
+        }
+    }
+
+    /// `\w`: word chars, only 0-9, A-Z, a-z and _ if unicode is false
+    pub fn word(unicode: bool) -> Self {
+        if unicode {
+            CharClass::from_ranges(perl_word::PERL_WORD)
+        } else {
+            CharClass::from_ranges(&[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])
+        }
+    }
+
+    /// `\s`: white spaces, only \t, \n, \v, \f, \r and ' ' if unicode is false
+    pub fn space(unicode: bool) -> Self {
+        if unicode {
+            CharClass::from_ranges(perl_space::WHITE_SPACE)
+        } else {
+            CharClass::from_ranges(&[('\t', '\r'), (' ', ' ')])
+        }
+    }
+
+    /// class of a Unicode general category or script, such as `L`, `Letter`, `Greek` or `Grek`.
+    ///
+    /// the property may be given explicitly, such as `gc=Lu` and `Script=Greek`.
+    /// names are compared ignoring case, spaces, `_` and `-`.
+    /// returns None if name is unknown.
+    pub fn property(name: &str) -> Option<Self> {
+        let normalize = |s: &str| -> String {
+            s.chars()
+                .filter(|c| !matches!(c, ' ' | '_' | '-'))
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+
+        let (property, value) = match name.split_once(['=', ':']) {
+            Some((property, value)) => (Some(normalize(property)), normalize(value)),
+            None => (None, normalize(name)),
+        };
+        let gc = || property_value("General_Category", &value, general_category::BY_NAME);
+        let sc = || property_value("Script", &value, script::BY_NAME);
+
+        let ranges = match property.as_deref() {
+            None => gc().or_else(sc)?,
+            Some("gc" | "generalcategory") => gc()?,
+            Some("sc" | "script") => sc()?,
+            Some(_) => return None,
+        };
+        Some(CharClass::from_ranges(ranges))
+    }
+
+    /// class of a POSIX bracket expression such as `[:alpha:]`, with ASCII chars only.
+    ///
+    /// returns None if name is unknown.
+    pub fn posix(name: &str) -> Option<Self> {
+        let ranges: &[(char, char)] = match name {
+            "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+            "alpha" => &[('A', 'Z'), ('a', 'z')],
+            "ascii" => &[('\0', '\x7F')],
+            "blank" => &[('\t', '\t'), (' ', ' ')],
+            "cntrl" => &[('\0', '\x1F'), ('\x7F', '\x7F')],
+            "digit" => &[('0', '9')],
+            "graph" => &[('!', '~')],
+            "lower" => &[('a', 'z')],
+            "print" => &[(' ', '~')],
+            "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "space" => &[('\t', '\r'), (' ', ' ')],
+            "upper" => &[('A', 'Z')],
+            "word" => &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+            "xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+            _ => return None,
+        };
+        Some(CharClass::from_ranges(ranges))
+    }
+
+    /// add chars from lo to hi (inclusive)
+    pub fn push(&mut self, lo: char, hi: char) {
+        self.ranges.push((lo, hi));
+        self.canonicalize();
+    }
+
+    /// add all chars of other
+    pub fn union(&mut self, other: &CharClass) {
+        self.ranges.extend_from_slice(&other.ranges);
+        self.canonicalize();
+    }
+
+    /// add the other cases of all chars in the class, such as 'A' for 'a' and 'K' (Kelvin sign) for 'k'.
+    ///
+    /// uses Unicode simple case folding, so a char never matches a string such as 'ß' and "ss".
+    pub fn case_fold(&mut self) {
+        let table = case_folding_simple::CASE_FOLDING_SIMPLE;
+        let mut folded = Vec::new();
+        for &(lo, hi) in self.ranges.iter() {
+            let start = table.partition_point(|&(c, _)| c < lo);
+            for &(_, others) in table[start..].iter().take_while(|&&(c, _)| c <= hi) {
+                folded.extend(others.iter().map(|&o| (o, o)));
+            }
+        }
+        self.ranges.append(&mut folded);
+        self.canonicalize();
+    }
+
+    /// keep only the chars also in other
+    pub fn intersect(&mut self, other: &CharClass) {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while let (Some(&(lo1, hi1)), Some(&(lo2, hi2))) = (self.ranges.get(i), other.ranges.get(j))
+        {
+            let (lo, hi) = (lo1.max(lo2), hi1.min(hi2));
+            if lo <= hi {
+                ranges.push((lo, hi));
+            }
+            // the range ending first cannot overlap the next range of the other class
+            if hi1 < hi2 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        self.ranges = ranges;
+        self.canonicalize();
+    }
+
+    /// remove the chars in other
+    pub fn difference(&mut self, other: &CharClass) {
+        let mut rest = other.clone();
+        rest.negate();
+        self.intersect(&rest);
+    }
+
+    /// sort ranges and merge those overlapping or touching each other
+    fn canonicalize(&mut self) {
+        self.ranges.retain(|(lo, hi)| lo <= hi);
+        self.ranges.sort_unstable();
+
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(self.ranges.len());
+        for &(lo, hi) in self.ranges.iter() {
+            match merged.last_mut() {
+                Some((_, last)) if next_char(*last).is_none_or(|n| n >= lo) => {
+                    *last = (*last).max(hi);
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// replace the class with the chars it does not contain
+    pub fn negate(&mut self) {
+        let mut negated = Vec::with_capacity(self.ranges.len() + 1);
+        let mut lo = Some('\0');
+        for &(start, end) in self.ranges.iter() {
+            if let (Some(l), Some(h)) = (lo, prev_char(start)) {
+                if l <= h {
+                    negated.push((l, h));
+                }
+            }
+            lo = next_char(end);
+        }
+        if let Some(l) = lo {
+            negated.push((l, char::MAX));
+        }
+        self.ranges = negated;
+    }
+
+    /// true if c is in the class
+    pub fn contains(&self, c: char) -> bool {
+        in_ranges(&self.ranges, c)
+    }
+}
+
+/// write the class in bracket syntax, for example `[0-9A-Z_a-z]`
+impl Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_char = |f: &mut fmt::Formatter<'_>, c: char| match c {
+            '\\' | ']' | '[' | '-' | '^' => write!(f, "\\{c}"),
+            _ => write!(f, "{}", c.escape_debug()),
+        };
+
+        write!(f, "[")?;
+        for &(lo, hi) in self.ranges.iter() {
+            write_char(f, lo)?;
+            if lo != hi {
+                write!(f, "-")?;
+                write_char(f, hi)?;
+            }
+        }
+        write!(f, "]")
+    }
+}
@@ -1,57 +1,101 @@
-use super::{parser::AST, Instruction};
+use super::{class::CharClass, parser::AST, Assertion, Instruction, Look};
 use crate::helper::safe_add;
 use std::{
     error::Error,
     fmt::{self, Display},
 };
 
-#[derive(Debug)]
+/// default upper bound on the number of generated instructions
+pub const DEFAULT_SIZE_LIMIT: usize = 100_000;
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum CodeGenError {
     PCOverFlow,
+    SlotOverFlow,
     FailStar,
     FailOr,
     FailQuestion,
+    FailRepeat,
+    FailLook,
+    FailAtomic,
+    TooLarge(usize),           // program has more instructions than the limit
+    Unsupported(&'static str), // feature that width-first search cannot run
 }
 
 impl Display for CodeGenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CodeGenError: {:?}", self)
+        match self {
+            CodeGenError::TooLarge(limit) => write!(
+                f,
+                "CodeGenError: program is larger than the size limit of {limit} instructions"
+            ),
+            CodeGenError::Unsupported(feature) => write!(
+                f,
+                "CodeGenError: {feature} is unsupported by this engine (width-first search)"
+            ),
+            _ => write!(f, "CodeGenError: {:?}", self),
+        }
     }
 }
 
 impl Error for CodeGenError {}
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 struct Generator {
     pc: usize,
     insts: Vec<Instruction>,
+    size_limit: usize, // upper bound on the number of instructions
+    use_dfs: bool,     // false if the code is run by width-first search
 }
 
 impl Generator {
     /// increment program counter (pc)
     fn inc_pc(&mut self) -> Result<(), CodeGenError> {
-        safe_add(&mut self.pc, &1, || CodeGenError::PCOverFlow)
+        safe_add(&mut self.pc, &1, || CodeGenError::PCOverFlow)?;
+        if self.pc > self.size_limit {
+            // example: (a{1000}){1000}
+            return Err(CodeGenError::TooLarge(self.size_limit));
+        }
+        Ok(())
     }
 
     /// generate code
     fn gen_expr(&mut self, ast: &AST) -> Result<(), CodeGenError> {
         match ast {
             AST::Char(c) => self.gen_char(*c)?,
-            AST::Plus(e) => self.gen_plus(e)?,
-            AST::Star(e1) => match &**e1 {
-                AST::Star(_) => self.gen_expr(e1)?,
+            AST::Plus(e, greedy) => self.gen_plus(e, *greedy)?,
+            // (e*)* is the same as e* if both are greedy or both are lazy
+            AST::Star(e1, greedy) => match &**e1 {
+                AST::Star(_, g) if g == greedy => self.gen_expr(e1)?,
                 AST::Seq(e2) if e2.len() == 1 => {
-                    if let Some(e3 @ AST::Star(_)) = e2.get(0) {
-                        self.gen_expr(e3)?
+                    if let Some(e3 @ AST::Star(_, g)) = e2.first() {
+                        if g == greedy {
+                            self.gen_expr(e3)?
+                        } else {
+                            self.gen_star(e1, *greedy)?
+                        }
                     } else {
-                        self.gen_star(e1)?
+                        self.gen_star(e1, *greedy)?
                     }
                 }
-                e => self.gen_star(e)?,
+                e => self.gen_star(e, *greedy)?,
             },
-            AST::Question(e) => self.gen_question(e)?,
+            AST::Question(e, greedy) => self.gen_question(e, *greedy)?,
             AST::Or(e1, e2) => self.gen_or(e1, e2)?,
             AST::Seq(v) => self.gen_seq(v)?,
+            AST::Capture(n, e) => self.gen_capture(*n, e)?,
+            AST::Class(class) => self.gen_class(class)?,
+            AST::Any(dotall) => self.gen_any(*dotall)?,
+            AST::Assert(assertion) => self.gen_assert(*assertion)?,
+            AST::Look(look, e) => self.gen_look(*look, e)?,
+            AST::Atomic(e) => self.gen_atomic(e)?,
+            AST::Backref(group, case_insensitive) => self.gen_backref(*group, *case_insensitive)?,
+            AST::Repeat {
+                e,
+                min,
+                max,
+                greedy,
+            } => self.gen_repeat(e, *min, *max, *greedy)?,
         }
 
         Ok(())
@@ -73,6 +117,151 @@ impl Generator {
         Ok(())
     }
 
+    /// generate code matching any char
+    fn gen_any(&mut self, dotall: bool) -> Result<(), CodeGenError> {
+        let inst = Instruction::Any(dotall);
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// generate zero-width assertion code
+    fn gen_assert(&mut self, assertion: Assertion) -> Result<(), CodeGenError> {
+        let inst = Instruction::Assert(assertion);
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// generate character class code
+    fn gen_class(&mut self, class: &CharClass) -> Result<(), CodeGenError> {
+        let inst = Instruction::Class(class.clone());
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// generate lookaround codes like below
+    ///
+    /// ```text
+    ///     look L1
+    ///     code of e
+    ///     look end
+    /// L1:
+    /// ```
+    ///
+    /// the code of e is run as a sub-program that does not move the position in the input string.
+    /// for lookbehind, the sub-program is run from each position between min and max chars before,
+    /// and must end at the current position.
+    fn gen_look(&mut self, look: Look, e: &AST) -> Result<(), CodeGenError> {
+        if !self.use_dfs {
+            return Err(CodeGenError::Unsupported("lookaround"));
+        }
+
+        let (min, max) = match (look, e.length()) {
+            (Look::Ahead | Look::NegAhead, _) => (0, 0),
+            (Look::Behind | Look::NegBehind, (min, Some(max))) => (min, max),
+            // unbounded lookbehind is rejected by the parser
+            _ => return Err(CodeGenError::FailLook),
+        };
+
+        // look L1
+        let look_addr = self.pc;
+        self.inc_pc()?;
+        self.insts.push(Instruction::Look {
+            look,
+            min,
+            max,
+            next: 0, // assume that L1 = 0
+        });
+
+        // code of e
+        self.gen_expr(e)?;
+
+        // look end
+        self.inc_pc()?;
+        self.insts.push(Instruction::LookEnd);
+
+        // set L1
+        if let Some(Instruction::Look { next, .. }) = self.insts.get_mut(look_addr) {
+            *next = self.pc;
+            Ok(())
+        } else {
+            Err(CodeGenError::FailLook)
+        }
+    }
+
+    /// generate atomic group codes like below
+    ///
+    /// ```text
+    ///     atomic L1
+    ///     code of e
+    ///     atomic end
+    /// L1:
+    /// ```
+    ///
+    /// the code of e is run as a sub-program, and only its first match is used;
+    /// if the rest of the expression fails, the other ways to match e are not tried.
+    fn gen_atomic(&mut self, e: &AST) -> Result<(), CodeGenError> {
+        if !self.use_dfs {
+            return Err(CodeGenError::Unsupported("atomic group"));
+        }
+
+        // atomic L1
+        let atomic_addr = self.pc;
+        self.inc_pc()?;
+        self.insts.push(Instruction::Atomic(0)); // assume that L1 = 0
+
+        // code of e
+        self.gen_expr(e)?;
+
+        // atomic end
+        self.inc_pc()?;
+        self.insts.push(Instruction::AtomicEnd);
+
+        // set L1
+        if let Some(Instruction::Atomic(next)) = self.insts.get_mut(atomic_addr) {
+            *next = self.pc;
+            Ok(())
+        } else {
+            Err(CodeGenError::FailAtomic)
+        }
+    }
+
+    /// generate backreference code
+    fn gen_backref(&mut self, group: usize, case_insensitive: bool) -> Result<(), CodeGenError> {
+        if !self.use_dfs {
+            return Err(CodeGenError::Unsupported("backreference"));
+        }
+
+        self.insts
+            .push(Instruction::Backref(group, case_insensitive));
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// generate capture group codes like below
+    ///
+    /// ```text
+    ///     save 2n
+    ///     code of e
+    ///     save 2n+1
+    /// ```
+    fn gen_capture(&mut self, n: usize, e: &AST) -> Result<(), CodeGenError> {
+        let slot = n.checked_mul(2).ok_or(CodeGenError::SlotOverFlow)?;
+        self.gen_save(slot)?;
+        self.gen_expr(e)?;
+        self.gen_save(slot + 1)?;
+        Ok(())
+    }
+
+    /// generate save code
+    fn gen_save(&mut self, slot: usize) -> Result<(), CodeGenError> {
+        self.insts.push(Instruction::Save(slot));
+        self.inc_pc()?;
+        Ok(())
+    }
+
     /// generate OR codes like below
     ///
     /// ```text
@@ -117,6 +306,23 @@ impl Generator {
         Ok(())
     }
 
+    /// set the address of the branch that skips e to the Split at addr.
+    ///
+    /// the Split is generated as `split L1, 0` where L1 is the branch that runs e,
+    /// and lazy quantifiers try skipping first, so the two branches are swapped.
+    fn set_skip(&mut self, addr: usize, greedy: bool) -> bool {
+        let skip = self.pc;
+        if let Some(Instruction::Split(l1, l2)) = self.insts.get_mut(addr) {
+            *l2 = skip;
+            if !greedy {
+                std::mem::swap(l1, l2);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
     /// generate ? code like bilow
     ///
     /// ```text
@@ -124,7 +330,9 @@ impl Generator {
     /// L1: code of e
     /// L2:
     /// ```
-    fn gen_question(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// if lazy, `split L2, L1` is generated instead.
+    fn gen_question(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
         // split L1, L2
         let split_addr = self.pc;
         self.inc_pc()?;
@@ -135,8 +343,7 @@ impl Generator {
         self.gen_expr(e)?;
 
         // set L2
-        if let Some(Instruction::Split(_, l2)) = self.insts.get_mut(split_addr) {
-            *l2 = self.pc;
+        if self.set_skip(split_addr, greedy) {
             Ok(())
         } else {
             Err(CodeGenError::FailQuestion)
@@ -146,19 +353,24 @@ impl Generator {
     /// generate + code like below
     ///
     /// ```text
-    /// L1: split L2, L3
-    /// L2: code of e
-    ///     jump L1
-    /// L3:
+    /// L1: code of e
+    ///     split L1, L2
+    /// L2:
     /// ```
-    fn gen_plus(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// if lazy, `split L2, L1` is generated instead.
+    fn gen_plus(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
         // L1: code of e
         let l1 = self.pc;
         self.gen_expr(e)?;
 
         // split L1, L2
         self.inc_pc()?;
-        let split = Instruction::Split(l1, self.pc);
+        let split = if greedy {
+            Instruction::Split(l1, self.pc)
+        } else {
+            Instruction::Split(self.pc, l1)
+        };
         self.insts.push(split);
 
         Ok(())
@@ -172,7 +384,34 @@ impl Generator {
     ///     jump L1
     /// L3:
     /// ```
-    fn gen_star(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// if lazy, `split L3, L2` is generated instead.
+    ///
+    /// if e can match the empty string, `(?:e+)?` is generated instead like below,
+    /// so that the loop follows leftmost-first priority as in the regex crate:
+    /// a first iteration matching the empty string ends the loop with an empty match,
+    /// while a later empty iteration fails and the other alternatives of e are tried.
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: code of e
+    ///     split L1, L2
+    /// L2:
+    /// ```
+    fn gen_star(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
+        if e.length().0 == 0 {
+            // example: (|a)* matches the empty string at the start of "aa"
+            let split_addr = self.pc;
+            self.inc_pc()?;
+            self.insts.push(Instruction::Split(self.pc, 0)); // assume that L2 = 0
+            self.gen_plus(e, greedy)?;
+            return if self.set_skip(split_addr, greedy) {
+                Ok(())
+            } else {
+                Err(CodeGenError::FailStar)
+            };
+        }
+
         // L1: split L2, L3
         let l1 = self.pc;
         self.inc_pc()?;
@@ -187,14 +426,70 @@ impl Generator {
         self.insts.push(Instruction::Jump(l1));
 
         // set L3
-        if let Some(Instruction::Split(_, l3)) = self.insts.get_mut(l1) {
-            *l3 = self.pc;
+        if self.set_skip(l1, greedy) {
             Ok(())
         } else {
             Err(CodeGenError::FailStar)
         }
     }
 
+    /// generate {min,max} codes by repeating the code of e.
+    ///
+    /// {n,} is n copies of e followed by e*, and {n,m} is n copies of e followed by m - n nested e? like below
+    ///
+    /// ```text
+    ///     code of e (n times)
+    ///     split L1, L3
+    /// L1: code of e
+    ///     split L2, L3
+    /// L2: code of e
+    /// L3:
+    /// ```
+    ///
+    /// if lazy, the branches of each Split are swapped.
+    fn gen_repeat(
+        &mut self,
+        e: &AST,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    ) -> Result<(), CodeGenError> {
+        // e without code, such as (?:) and a{0}, only matches the empty string,
+        // so any number of copies is the same as none.
+        // example: (?:){99999999999}
+        if !e.generates_code() {
+            return Ok(());
+        }
+
+        for _ in 0..min {
+            self.gen_expr(e)?;
+        }
+
+        let max = if let Some(max) = max {
+            max
+        } else {
+            return self.gen_star(e, greedy);
+        };
+
+        // split Ln, L3
+        let mut split_addrs = Vec::new();
+        for _ in min..max {
+            split_addrs.push(self.pc);
+            self.inc_pc()?;
+            self.insts.push(Instruction::Split(self.pc, 0)); // assume that L3 = 0
+            self.gen_expr(e)?;
+        }
+
+        // set L3
+        for addr in split_addrs {
+            if !self.set_skip(addr, greedy) {
+                return Err(CodeGenError::FailRepeat);
+            }
+        }
+
+        Ok(())
+    }
+
     /// entry point of code generation
     fn gen_code(&mut self, ast: &AST) -> Result<(), CodeGenError> {
         self.gen_expr(ast)?;
@@ -205,8 +500,20 @@ impl Generator {
 }
 
 /// function to generate code
-pub fn get_code(ast: &AST) -> Result<Vec<Instruction>, CodeGenError> {
-    let mut generator = Generator::default();
+///
+/// returns `CodeGenError::TooLarge` if more than size_limit instructions are needed,
+/// and `CodeGenError::Unsupported` if use_dfs is false and ast has a feature that width-first search cannot run.
+pub fn get_code(
+    ast: &AST,
+    size_limit: usize,
+    use_dfs: bool,
+) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator {
+        pc: 0,
+        insts: Vec::new(),
+        size_limit,
+        use_dfs,
+    };
     generator.gen_code(ast)?;
     Ok(generator.insts)
 }
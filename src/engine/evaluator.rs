@@ -1,10 +1,13 @@
 //! receives instruction string and input string and executes matching
-use super::Instruction;
+use super::{
+    class::{equal_fold, is_word_char},
+    Assertion, Instruction, Look, MatchKind,
+};
 use crate::helper::safe_add;
 use std::{
-    collections::VecDeque,
     error::Error,
     fmt::{self, Display},
+    mem::replace,
 };
 
 #[derive(Debug)]
@@ -12,6 +15,7 @@ pub enum EvalError {
     PCOverFlow,
     SPOverFlow,
     InvalidPC,
+    InvalidSlot,
 }
 
 impl Display for EvalError {
@@ -22,111 +26,459 @@ impl Display for EvalError {
 
 impl Error for EvalError {}
 
-/// match by DFS
-fn eval_depth(
-    inst: &[Instruction],
-    line: &[char],
-    mut pc: usize,
-    mut sp: usize,
-) -> Result<bool, EvalError> {
-    loop {
-        let next_i = if let Some(i) = inst.get(pc) {
-            i
-        } else {
-            return Err(EvalError::InvalidPC);
-        };
-
-        match next_i {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if sp_c == c {
+/// positions saved by Save instructions, indexed by slot.
+///
+/// slot 2n is the start and slot 2n + 1 is the end of capture group n,
+/// and group 0 is the whole match.
+pub type Slots = Vec<Option<usize>>;
+
+/// write sp into slot of slots
+fn save(slots: &mut Slots, slot: usize, sp: usize) -> Result<Option<usize>, EvalError> {
+    if let Some(s) = slots.get_mut(slot) {
+        Ok(s.replace(sp))
+    } else {
+        Err(EvalError::InvalidSlot)
+    }
+}
+
+/// true if the instruction consuming a char accepts c
+fn accepts(inst: &Instruction, c: char) -> bool {
+    match inst {
+        Instruction::Char(i_c) => *i_c == c,
+        Instruction::Class(class) => class.contains(c),
+        Instruction::Any(dotall) => *dotall || c != '\n',
+        _ => false,
+    }
+}
+
+/// true if sp of line is between a word char and a non-word char, or the start or end of line
+fn at_word_boundary(line: &[char], sp: usize, unicode: bool) -> bool {
+    let before = sp
+        .checked_sub(1)
+        .and_then(|i| line.get(i))
+        .is_some_and(|c| is_word_char(*c, unicode));
+    let after = line.get(sp).is_some_and(|c| is_word_char(*c, unicode));
+    before != after
+}
+
+/// true if assertion holds at sp of line
+fn holds(assertion: Assertion, line: &[char], sp: usize) -> bool {
+    match assertion {
+        Assertion::StartText => sp == 0,
+        Assertion::EndText => sp == line.len(),
+        Assertion::StartLine => sp == 0 || line.get(sp - 1) == Some(&'\n'),
+        Assertion::EndLine => sp == line.len() || line.get(sp) == Some(&'\n'),
+        Assertion::WordBoundary(unicode) => at_word_boundary(line, sp, unicode),
+        Assertion::NotWordBoundary(unicode) => !at_word_boundary(line, sp, unicode),
+    }
+}
+
+/// context of matching by DFS
+struct DepthFirst<'a> {
+    inst: &'a [Instruction],
+    line: &'a [char],
+    kind: MatchKind,
+    slots: Slots,
+    closed: Slots, // slots of the capture groups that have ended, read by Backref
+    splits: Vec<Option<usize>>, // splits[pc] is the sp at which Split at pc is being tried
+    look_ends: Vec<Option<usize>>, // sp where each running lookaround must end, None for lookahead
+    atomic_ends: Vec<Option<usize>>, // sp where each running atomic group ended
+}
+
+impl DepthFirst<'_> {
+    /// run the sub-program of lookaround starting at pc from sp.
+    ///
+    /// returns true if the sub-program reaches LookEnd, at end if it is given.
+    fn eval_look(&mut self, pc: usize, sp: usize, end: Option<usize>) -> Result<bool, EvalError> {
+        self.look_ends.push(end);
+        let result = self.eval(pc, sp);
+        self.look_ends.pop();
+        result
+    }
+
+    /// match by DFS
+    ///
+    /// returns true if the match succeeds, and then the captured positions are left in slots.
+    fn eval(&mut self, mut pc: usize, mut sp: usize) -> Result<bool, EvalError> {
+        loop {
+            let next_i = if let Some(i) = self.inst.get(pc) {
+                i
+            } else {
+                return Err(EvalError::InvalidPC);
+            };
+
+            match next_i {
+                Instruction::Char(_) | Instruction::Class(_) | Instruction::Any(_) => {
+                    if let Some(sp_c) = self.line.get(sp) {
+                        if accepts(next_i, *sp_c) {
+                            safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                            safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+                        } else {
+                            return Ok(false);
+                        }
+                    } else {
+                        return Ok(false);
+                    }
+                }
+                Instruction::Jump(addr) => {
+                    pc = *addr;
+                }
+                Instruction::Assert(assertion) => {
+                    if holds(*assertion, self.line, sp) {
                         safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
                     } else {
                         return Ok(false);
                     }
-                } else {
-                    return Ok(false);
                 }
-            }
-            Instruction::Jump(addr) => {
-                pc = *addr;
-            }
-            Instruction::Match => {
-                return Ok(true);
-            }
-            Instruction::Split(addr1, addr2) => {
-                if eval_depth(inst, line, *addr1, sp)? || eval_depth(inst, line, *addr2, sp)? {
-                    return Ok(true);
-                } else {
-                    return Ok(false);
+                Instruction::Match => {
+                    // with MatchKind::Full, the whole line must be consumed
+                    if self.kind != MatchKind::Full || sp == self.line.len() {
+                        save(&mut self.slots, 1, sp)?;
+                        return Ok(true);
+                    } else {
+                        return Ok(false);
+                    }
+                }
+                Instruction::Look {
+                    look,
+                    min,
+                    max,
+                    next,
+                } => {
+                    let mut sub_pc = pc;
+                    safe_add(&mut sub_pc, &1, || EvalError::PCOverFlow)?;
+
+                    // captures in the sub-program are kept only if the whole match succeeds
+                    let saved = (self.slots.clone(), self.closed.clone());
+                    let found = match look {
+                        Look::Ahead | Look::NegAhead => self.eval_look(sub_pc, sp, None)?,
+                        Look::Behind | Look::NegBehind => {
+                            let mut found = false;
+                            for len in *min..=(*max).min(sp) {
+                                if self.eval_look(sub_pc, sp - len, Some(sp))? {
+                                    found = true;
+                                    break;
+                                }
+                            }
+                            found
+                        }
+                    };
+
+                    let negated = matches!(look, Look::NegAhead | Look::NegBehind);
+                    let result = found != negated && self.eval(*next, sp)?;
+                    if !result {
+                        (self.slots, self.closed) = saved;
+                    }
+                    return Ok(result);
+                }
+                Instruction::Backref(group, case_insensitive) => {
+                    // a group that has not ended yet never matches
+                    let (start, end) =
+                        match (self.closed.get(group * 2), self.closed.get(group * 2 + 1)) {
+                            (Some(Some(start)), Some(Some(end))) => (*start, *end),
+                            (Some(_), Some(_)) => return Ok(false),
+                            _ => return Err(EvalError::InvalidSlot),
+                        };
+
+                    let text = if let Some(text) = self.line.get(start..end) {
+                        text
+                    } else {
+                        return Ok(false);
+                    };
+                    let rest = &self.line[sp..];
+                    let same = text.len() <= rest.len()
+                        && text.iter().zip(rest).all(|(a, b)| {
+                            if *case_insensitive {
+                                equal_fold(*a, *b)
+                            } else {
+                                a == b
+                            }
+                        });
+                    if !same {
+                        return Ok(false);
+                    }
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    safe_add(&mut sp, &text.len(), || EvalError::SPOverFlow)?;
+                }
+                Instruction::Atomic(next) => {
+                    let mut sub_pc = pc;
+                    safe_add(&mut sub_pc, &1, || EvalError::PCOverFlow)?;
+
+                    // only the first way the sub-program matches is tried
+                    let saved = (self.slots.clone(), self.closed.clone());
+                    self.atomic_ends.push(None);
+                    let found = self.eval(sub_pc, sp);
+                    let end = self.atomic_ends.pop().flatten();
+
+                    let result = match (found?, end) {
+                        (true, Some(end)) => self.eval(*next, end)?,
+                        _ => false,
+                    };
+                    if !result {
+                        (self.slots, self.closed) = saved;
+                    }
+                    return Ok(result);
+                }
+                Instruction::AtomicEnd => {
+                    return match self.atomic_ends.last_mut() {
+                        Some(end) => {
+                            *end = Some(sp);
+                            Ok(true)
+                        }
+                        None => Err(EvalError::InvalidPC),
+                    };
+                }
+                Instruction::LookEnd => {
+                    return match self.look_ends.last() {
+                        Some(end) => Ok(end.is_none_or(|end| end == sp)),
+                        None => Err(EvalError::InvalidPC),
+                    };
+                }
+                Instruction::Split(addr1, addr2) => {
+                    // coming back to the same Split without consuming any char is an infinite loop.
+                    // example: (a*)*
+                    let prev = match self.splits.get_mut(pc) {
+                        Some(Some(s)) if *s == sp => return Ok(false),
+                        Some(s) => s.replace(sp),
+                        None => return Err(EvalError::InvalidPC),
+                    };
+
+                    let result = self.eval(*addr1, sp)? || self.eval(*addr2, sp)?;
+                    self.splits[pc] = prev;
+                    return Ok(result);
+                }
+                Instruction::Save(slot) => {
+                    // restore the previous position if the rest of the match fails
+                    let prev = save(&mut self.slots, *slot, sp)?;
+
+                    // the start slot is overwritten when a group in a loop is entered again,
+                    // so the span of the group is also kept in closed when it ends.
+                    // example: (?:(a|b\1)c)+
+                    let prev_closed = if slot % 2 == 1 {
+                        let start = replace(&mut self.closed[slot - 1], self.slots[slot - 1]);
+                        let end = save(&mut self.closed, *slot, sp)?;
+                        Some((start, end))
+                    } else {
+                        None
+                    };
+
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    if self.eval(pc, sp)? {
+                        return Ok(true);
+                    } else {
+                        self.slots[*slot] = prev;
+                        if let Some((start, end)) = prev_closed {
+                            self.closed[slot - 1] = start;
+                            self.closed[*slot] = end;
+                        }
+                        return Ok(false);
+                    }
                 }
             }
         }
     }
 }
 
-/// match by bfs
-fn eval_width(inst: &[Instruction], line: &[char]) -> Result<bool, EvalError> {
-    let mut ctx_dq = VecDeque::new();
+/// match by DFS
+///
+/// the match is tried from each start position in turn, and the first one that succeeds is returned.
+fn eval_depth(
+    inst: &[Instruction],
+    line: &[char],
+    kind: MatchKind,
+    nslots: usize,
+) -> Result<Option<Slots>, EvalError> {
+    let last = if kind == MatchKind::Search {
+        line.len()
+    } else {
+        0
+    };
+
+    let mut ctx = DepthFirst {
+        inst,
+        line,
+        kind,
+        slots: vec![None; nslots],
+        closed: vec![None; nslots],
+        splits: vec![None; inst.len()],
+        look_ends: Vec::new(),
+        atomic_ends: Vec::new(),
+    };
+    for start in 0..=last {
+        save(&mut ctx.slots, 0, start)?;
+        if ctx.eval(0, start)? {
+            return Ok(Some(ctx.slots));
+        }
+    }
+
+    Ok(None)
+}
+
+/// set of threads that are alive at the same position of the input.
+///
+/// each pc is visited at most once, so the number of threads never exceeds the number of instructions.
+struct Threads {
+    runnable: Vec<(usize, Slots)>, // threads waiting on a char or Match, in priority order
+    visited: Vec<usize>,           // every pc reached while adding threads
+    on: Vec<bool>,                 // on[pc] is true if pc is in visited
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads {
+            runnable: Vec::with_capacity(len),
+            visited: Vec::with_capacity(len),
+            on: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        for pc in self.visited.drain(..) {
+            self.on[pc] = false;
+        }
+        self.runnable.clear();
+    }
+}
+
+/// add a thread starting at pc to threads,
+/// following Jump, Split, Save and Assert so that only threads waiting on a char or Match are kept.
+///
+/// threads are added in the order depth-first search would try them,
+/// so a thread that comes earlier has higher priority.
+fn add_thread(
+    inst: &[Instruction],
+    line: &[char],
+    threads: &mut Threads,
+    pc: usize,
+    sp: usize,
+    mut slots: Slots,
+) -> Result<(), EvalError> {
+    match threads.on.get(pc) {
+        Some(true) => return Ok(()),
+        Some(false) => {
+            threads.on[pc] = true;
+            threads.visited.push(pc);
+        }
+        None => return Err(EvalError::InvalidPC),
+    }
+
+    match &inst[pc] {
+        Instruction::Jump(addr) => add_thread(inst, line, threads, *addr, sp, slots)?,
+        Instruction::Split(addr1, addr2) => {
+            add_thread(inst, line, threads, *addr1, sp, slots.clone())?;
+            add_thread(inst, line, threads, *addr2, sp, slots)?;
+        }
+        Instruction::Save(slot) => {
+            save(&mut slots, *slot, sp)?;
+            let mut next_pc = pc;
+            safe_add(&mut next_pc, &1, || EvalError::PCOverFlow)?;
+            add_thread(inst, line, threads, next_pc, sp, slots)?;
+        }
+        Instruction::Assert(assertion) => {
+            if holds(*assertion, line, sp) {
+                let mut next_pc = pc;
+                safe_add(&mut next_pc, &1, || EvalError::PCOverFlow)?;
+                add_thread(inst, line, threads, next_pc, sp, slots)?;
+            }
+        }
+        Instruction::Char(_) | Instruction::Class(_) | Instruction::Any(_) | Instruction::Match => {
+            threads.runnable.push((pc, slots))
+        }
+        // these are rejected when the code is generated for width-first search
+        Instruction::Look { .. }
+        | Instruction::LookEnd
+        | Instruction::Backref(_, _)
+        | Instruction::Atomic(_)
+        | Instruction::AtomicEnd => return Err(EvalError::InvalidPC),
+    }
 
-    let mut pc = 0usize;
+    Ok(())
+}
+
+/// match by bfs
+///
+/// all threads advance through line in lockstep, one character at a time.
+/// with MatchKind::Search, a new thread is started at every position so that a match can begin anywhere in line.
+///
+/// when a thread matches, the threads with lower priority are discarded
+/// and the remaining ones keep running to find the match depth-first search would find.
+fn eval_width(
+    inst: &[Instruction],
+    line: &[char],
+    kind: MatchKind,
+    nslots: usize,
+) -> Result<Option<Slots>, EvalError> {
+    let mut current = Threads::new(inst.len());
+    let mut next = Threads::new(inst.len());
     let mut sp = 0usize;
-    ctx_dq.push_back((pc, sp));
+    let mut matched = None;
 
     loop {
-        if let Some((p, s)) = ctx_dq.pop_front() {
-            pc = p;
-            sp = s;
-        } else {
-            return Ok(false);
+        // a new thread has lower priority than the threads already running
+        if matched.is_none() && (sp == 0 || kind == MatchKind::Search) {
+            let mut slots = vec![None; nslots];
+            save(&mut slots, 0, sp)?;
+            add_thread(inst, line, &mut current, 0, sp, slots)?;
         }
-        let next_i = if let Some(i) = inst.get(pc) {
-            i
-        } else {
-            return Err(EvalError::InvalidPC);
-        };
-
-        match next_i {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if sp_c == c {
-                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
-                    } else {
-                        continue;
+
+        // no thread can match any more
+        // (with MatchKind::Search, new threads are started until a match is found)
+        if current.runnable.is_empty() && (matched.is_some() || kind != MatchKind::Search) {
+            return Ok(matched);
+        }
+
+        for (pc, slots) in current.runnable.drain(..) {
+            match &inst[pc] {
+                i @ (Instruction::Char(_) | Instruction::Class(_) | Instruction::Any(_)) => {
+                    if line.get(sp).is_some_and(|sp_c| accepts(i, *sp_c)) {
+                        let mut next_pc = pc;
+                        safe_add(&mut next_pc, &1, || EvalError::PCOverFlow)?;
+                        add_thread(inst, line, &mut next, next_pc, sp + 1, slots)?;
                     }
-                } else {
-                    continue;
                 }
-            }
-            Instruction::Jump(addr) => {
-                pc = *addr;
-            }
-            Instruction::Match => {
-                return Ok(true);
-            }
-            Instruction::Split(addr1, addr2) => {
-                ctx_dq.push_back((*addr1, sp));
-                pc = *addr2;
+                Instruction::Match => {
+                    // with MatchKind::Full, the whole line must be consumed
+                    if kind != MatchKind::Full || sp == line.len() {
+                        let mut slots = slots;
+                        save(&mut slots, 1, sp)?;
+                        matched = Some(slots);
+                        break;
+                    }
+                }
+                _ => return Err(EvalError::InvalidPC),
             }
         }
 
-        ctx_dq.push_back((pc, sp));
+        if sp >= line.len() {
+            return Ok(matched);
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        next.clear();
+        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
     }
 }
 
 /// function to evaluate a sequence of instructions.
 ///
 /// inst becomes an instruction string, and matches the input string line using that instruction string.
+/// kind decides where the match may start and end, see `MatchKind`.
 /// if is_depth is true, depth-first search is performed; if is_depth is false, width-first search is performed.
+/// nslots is the number of slots used by Save instructions, including the 2 slots of the whole match.
 ///
 /// returns Err if a runtime error occurs.
-/// returns Ok(true) if the match succeeds, Ok(false) if it fails.
-pub fn eval(inst: &[Instruction], line: &[char], use_dfs: bool) -> Result<bool, EvalError> {
+/// returns Ok(Some(slots)) with the char offsets of the leftmost match and its capture groups if the match succeeds,
+/// Ok(None) if it fails.
+pub fn eval(
+    inst: &[Instruction],
+    line: &[char],
+    use_dfs: bool,
+    kind: MatchKind,
+    nslots: usize,
+) -> Result<Option<Slots>, EvalError> {
     if use_dfs {
-        eval_depth(inst, line, 0, 0)
+        eval_depth(inst, line, kind, nslots)
     } else {
-        eval_width(inst, line)
+        eval_width(inst, line, kind, nslots)
     }
 }
@@ -1,29 +1,136 @@
 //! parse regular expression and convert to AST
 
+use super::{class::CharClass, Assertion, Look};
 use std::{
     error::Error,
     fmt::{self, Display},
+    iter::{Enumerate, Peekable},
     mem::take,
+    str,
 };
 
+/// chars of regular expression with their positions
+type Chars<'a> = Peekable<Enumerate<str::Chars<'a>>>;
+
 /// Type for implimenting AST(abstract syntax tree)
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum AST {
     Char(char),
-    Plus(Box<AST>),
-    Star(Box<AST>),
-    Question(Box<AST>),
+    Plus(Box<AST>, bool),     // bool is true if greedy, false if lazy (+?)
+    Star(Box<AST>, bool),     // bool is true if greedy, false if lazy (*?)
+    Question(Box<AST>, bool), // bool is true if greedy, false if lazy (??)
     Or(Box<AST>, Box<AST>),
     Seq(Vec<AST>),
+    Capture(usize, Box<AST>), // capture group, numbered from 1 in order of its left parenthesis
+    Class(CharClass),         // character class, such as [a-z]
+    Any(bool),                // any char, including '\n' if true
+    Assert(Assertion),        // zero-width assertion, such as ^ and $
+    Look(Look, Box<AST>),     // lookaround, such as (?=abc)
+    Backref(usize, bool),     // backreference to a capture group, case insensitive if true
+    Atomic(Box<AST>), // atomic group, such as (?>abc), or possessive quantifier, such as a*+
+    Repeat {
+        e: Box<AST>,
+        min: usize,
+        max: Option<usize>, // None if unbounded, such as {3,}
+        greedy: bool,       // false if lazy, such as {3,}?
+    },
+}
+
+impl AST {
+    /// shortest and longest number of chars matched, None for the longest if unbounded
+    pub fn length(&self) -> (usize, Option<usize>) {
+        match self {
+            AST::Char(_) | AST::Class(_) | AST::Any(_) => (1, Some(1)),
+            AST::Assert(_) | AST::Look(_, _) => (0, Some(0)),
+            AST::Plus(e, _) => (e.length().0, None),
+            AST::Backref(_, _) => (0, None),
+            AST::Star(_, _) => (0, None),
+            AST::Question(e, _) => (0, e.length().1),
+            AST::Or(e1, e2) => {
+                let (min1, max1) = e1.length();
+                let (min2, max2) = e2.length();
+                (min1.min(min2), max1.zip(max2).map(|(m1, m2)| m1.max(m2)))
+            }
+            AST::Seq(v) => v.iter().fold((0, Some(0)), |(min, max), e| {
+                let (e_min, e_max) = e.length();
+                let max = max.zip(e_max).and_then(|(m1, m2)| m1.checked_add(m2));
+                (min.saturating_add(e_min), max)
+            }),
+            AST::Capture(_, e) | AST::Atomic(e) => e.length(),
+            AST::Repeat { e, min, max, .. } => {
+                let (e_min, e_max) = e.length();
+                let max = match (max, e_max) {
+                    (_, Some(0)) => Some(0),
+                    (Some(max), Some(e_max)) => max.checked_mul(e_max),
+                    _ => None,
+                };
+                (e_min.saturating_mul(*min), max)
+            }
+        }
+    }
+
+    /// false if no instruction is generated for self, such as (?:) and a{0}
+    pub fn generates_code(&self) -> bool {
+        match self {
+            AST::Seq(v) => v.iter().any(|e| e.generates_code()),
+            AST::Repeat { e, max, .. } => *max != Some(0) && e.generates_code(),
+            _ => true,
+        }
+    }
+}
+
+/// options that change the meaning of the regular expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    pub dot_matches_new_line: bool, // `.` also matches '\n'
+    pub multi_line: bool,           // `^` and `$` match at the start and end of each line
+    pub unicode: bool,              // \d, \w and \s match Unicode chars, not only ASCII
+    pub case_insensitive: bool,     // letters match both upper and lower case
+    pub ignore_whitespace: bool,    // white spaces and `#` comments in the expression are ignored
 }
 
+impl Default for Flags {
+    fn default() -> Self {
+        Flags {
+            dot_matches_new_line: false,
+            multi_line: false,
+            unicode: true,
+            case_insensitive: false,
+            ignore_whitespace: false,
+        }
+    }
+}
+
+/// result of parsing a regular expression
 #[derive(Debug)]
+pub struct Parsed {
+    pub ast: AST,
+    pub names: Vec<Option<String>>, // name of each capture group, names[0] is the whole match
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
-    InvalidEscape(usize, char), // wrong escape
-    InvalidRightParen(usize),   // doesn't exist left par
-    NoPrev(usize),              // no expression before +, |, *, ?
-    NoRightParen,               // doesn't exist right par
-    Empty,                      // empty expression
+    InvalidEscape(usize, char),              // wrong escape
+    InvalidRightParen(usize),                // doesn't exist left par
+    NoPrev(usize),                           // no expression before +, *, ?, {n,m}
+    NoRightParen,                            // doesn't exist right par
+    UnknownGroup(usize),                     // unknown syntax after "(?"
+    InvalidGroupName(usize),                 // empty, unterminated or wrong char in group name
+    DuplicateGroupName(usize, String),       // group name used twice
+    NoRightBracket(usize),                   // doesn't exist right bracket of class at pos
+    EmptyClass(usize),                       // class without chars, such as []
+    InvalidClassRange(usize, char, char), // range whose start is greater than its end, such as [z-a]
+    InvalidRepeat(usize),                 // malformed or unterminated {n,m}
+    InvalidRepeatRange(usize, usize, usize), // {n,m} with n > m
+    RepeatOverflow(usize),                // count in {n,m} too large
+    InvalidCodePoint(usize),              // malformed \x or \u escape, such as \xZ1 or \u{12
+    CodePointOutOfRange(usize, u32),      // code point that is not a char, such as \u{110000}
+    InvalidFlag(usize),                   // unknown flag or misplaced `-` in (?flags)
+    UnboundedLookBehind(usize),           // lookbehind without the longest length, such as (?<=a+)
+    InvalidBackref(usize), // reference to a group not opened before, such as \2 in (a)\2
+    UnknownProperty(usize, String), // unknown Unicode property name, such as \p{Foo}
+    UnknownPosixClass(usize, String), // unknown name of POSIX class, such as [[:foo:]]
 }
 
 /// For displaying ParseError
@@ -42,8 +149,74 @@ impl Display for ParseError {
             ParseError::NoRightParen => {
                 write!(f, "ParseError: no right parenthesis")
             }
-            ParseError::Empty => {
-                write!(f, "ParseError: empty expression")
+            ParseError::UnknownGroup(pos) => {
+                write!(f, "ParseError: unknown group syntax: pos = {pos}")
+            }
+            ParseError::InvalidGroupName(pos) => {
+                write!(f, "ParseError: invalid group name: pos = {pos}")
+            }
+            ParseError::NoRightBracket(pos) => {
+                write!(f, "ParseError: no right bracket: pos = {pos}")
+            }
+            ParseError::EmptyClass(pos) => {
+                write!(f, "ParseError: empty character class: pos = {pos}")
+            }
+            ParseError::InvalidClassRange(pos, lo, hi) => {
+                write!(
+                    f,
+                    "ParseError: invalid character class range: pos = {pos}, range = '{lo}-{hi}'"
+                )
+            }
+            ParseError::InvalidRepeat(pos) => {
+                write!(f, "ParseError: invalid repetition: pos = {pos}")
+            }
+            ParseError::InvalidRepeatRange(pos, min, max) => {
+                write!(
+                    f,
+                    "ParseError: invalid repetition range: pos = {pos}, range = {{{min},{max}}}"
+                )
+            }
+            ParseError::RepeatOverflow(pos) => {
+                write!(f, "ParseError: repetition count overflow: pos = {pos}")
+            }
+            ParseError::UnknownProperty(pos, name) => {
+                write!(
+                    f,
+                    "ParseError: unknown Unicode property: pos = {pos}, name = '{name}'"
+                )
+            }
+            ParseError::UnknownPosixClass(pos, name) => {
+                write!(
+                    f,
+                    "ParseError: unknown POSIX class: pos = {pos}, name = '{name}'"
+                )
+            }
+            ParseError::InvalidBackref(pos) => {
+                write!(f, "ParseError: invalid backreference: pos = {pos}")
+            }
+            ParseError::UnboundedLookBehind(pos) => {
+                write!(
+                    f,
+                    "ParseError: lookbehind must have a bounded length: pos = {pos}"
+                )
+            }
+            ParseError::InvalidFlag(pos) => {
+                write!(f, "ParseError: invalid flag: pos = {pos}")
+            }
+            ParseError::InvalidCodePoint(pos) => {
+                write!(f, "ParseError: invalid code point escape: pos = {pos}")
+            }
+            ParseError::CodePointOutOfRange(pos, code) => {
+                write!(
+                    f,
+                    "ParseError: code point out of range: pos = {pos}, code point = U+{code:X}"
+                )
+            }
+            ParseError::DuplicateGroupName(pos, name) => {
+                write!(
+                    f,
+                    "ParseError: duplicate group name: pos = {pos}, name = '{name}'"
+                )
             }
         }
     }
@@ -51,40 +224,514 @@ impl Display for ParseError {
 
 impl Error for ParseError {}
 
-fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
+/// parse a hex code point just after `\x` or `\u`.
+///
+/// digits is the number of hex digits without braces, 2 for `\x41` and 4 for `\u0041`.
+/// with braces, such as `\u{1F600}`, 1 to 8 digits are allowed.
+/// pos is the position of `x` or `u`.
+fn parse_code_point(chars: &mut Chars, pos: usize, digits: usize) -> Result<char, ParseError> {
+    let braced = chars.next_if(|(_, c)| *c == '{').is_some();
+
+    let mut code: u32 = 0;
+    let mut n = 0;
+    loop {
+        match chars.peek() {
+            Some((_, '}')) if braced => {
+                chars.next();
+                break;
+            }
+            _ if !braced && n == digits => break,
+            Some((_, c)) if c.is_ascii_hexdigit() && n < 8 => {
+                code = code * 16 + c.to_digit(16).unwrap_or(0);
+                n += 1;
+                chars.next();
+            }
+            // example: \x4, \xZ1, \u{12, \u{123456789}
+            _ => return Err(ParseError::InvalidCodePoint(pos)),
+        }
+    }
+
+    if n == 0 {
+        // example: \u{}
+        return Err(ParseError::InvalidCodePoint(pos));
+    }
+
+    // example: \u{110000}, \u{D800}
+    char::from_u32(code).ok_or(ParseError::CodePointOutOfRange(pos, code))
+}
+
+/// parse escapes that stand for a single char, such as `\n`, `\t` and `\x41`.
+///
+/// pos is the position of c, the char just after `\`.
+/// return None if c does not start such an escape.
+fn parse_char_escape(chars: &mut Chars, pos: usize, c: char) -> Result<Option<char>, ParseError> {
+    let c = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'f' => '\x0C',
+        'v' => '\x0B',
+        'a' => '\x07',
+        'x' => parse_code_point(chars, pos, 2)?,
+        'u' => parse_code_point(chars, pos, 4)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(c))
+}
+
+/// true if c has a special meaning in regular expressions, and so must be escaped to match itself
+pub fn is_meta_char(c: char) -> bool {
+    matches!(
+        c,
+        '\\' | '('
+            | ')'
+            | '|'
+            | '+'
+            | '*'
+            | '?'
+            | '['
+            | ']'
+            | '.'
+            | '^'
+            | '$'
+            | '{'
+            | '}'
+            | '-'
+            | '#'
+            | '&'
+    )
+}
+
+/// AST matching c, and also its other cases if case_insensitive is set
+fn literal(c: char, flags: Flags) -> AST {
+    if flags.case_insensitive {
+        let single = CharClass::from_ranges(&[(c, c)]);
+        let mut class = single.clone();
+        class.case_fold();
+        if class != single {
+            return AST::Class(class);
+        }
+    }
+    AST::Char(c)
+}
+
+/// parse a backreference `\1` or `\k<name>`, just after `\`.
+///
+/// pos is the position of c, the first digit or `k`.
+/// names are the capture groups opened so far, so a group can be referred only after its `(`.
+fn parse_backref(
+    chars: &mut Chars,
+    pos: usize,
+    c: char,
+    flags: Flags,
+    names: &[Option<String>],
+) -> Result<AST, ParseError> {
+    let group = if c == 'k' {
+        let lt = chars
+            .next_if(|(_, c)| *c == '<')
+            .ok_or(ParseError::InvalidBackref(pos))?;
+        let name = parse_group_name(chars, lt.0)?;
+        // example: (?<a>x)\k<b>
+        names
+            .iter()
+            .position(|n| n.as_deref() == Some(name.as_str()))
+            .ok_or(ParseError::InvalidBackref(pos))?
+    } else {
+        let mut group = c as usize - '0' as usize;
+        while let Some((_, d)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            group = group
+                .checked_mul(10)
+                .and_then(|g| g.checked_add(d as usize - '0' as usize))
+                .ok_or(ParseError::InvalidBackref(pos))?;
+        }
+        group
+    };
+
+    if group >= names.len() {
+        // example: \1(a), (a)\2
+        return Err(ParseError::InvalidBackref(pos));
+    }
+    Ok(AST::Backref(group, flags.case_insensitive))
+}
+
+fn parse_escape(
+    chars: &mut Chars,
+    pos: usize,
+    c: char,
+    flags: Flags,
+    names: &[Option<String>],
+) -> Result<AST, ParseError> {
     match c {
-        '\\' | '(' | ')' | '+' | '*' | '?' => Ok(AST::Char(c)),
+        '1'..='9' | 'k' => parse_backref(chars, pos, c, flags, names),
+        'p' | 'P' => Ok(AST::Class(parse_property(chars, pos, c, flags)?)),
+        // escaped white space such as `\ ` is kept even if ignore_whitespace is set
+        c if is_meta_char(c) || c.is_whitespace() => Ok(literal(c, flags)),
+        'A' => Ok(AST::Assert(Assertion::StartText)),
+        'z' => Ok(AST::Assert(Assertion::EndText)),
+        'b' => Ok(AST::Assert(Assertion::WordBoundary(flags.unicode))),
+        'B' => Ok(AST::Assert(Assertion::NotWordBoundary(flags.unicode))),
         _ => {
-            let err = ParseError::InvalidEscape(pos, c);
-            Err(err)
+            if let Some(c) = parse_char_escape(chars, pos, c)? {
+                Ok(literal(c, flags))
+            } else if let Some(class) = perl_class(c, flags) {
+                Ok(AST::Class(class))
+            } else {
+                let err = ParseError::InvalidEscape(pos, c);
+                Err(err)
+            }
         }
     }
 }
 
+/// parse a Unicode property class, just after `\p` or `\P`.
+///
+/// pos is the position of c, which is `p` or `P` for the negation.
+///
+/// example: "\\p{Greek}", "\\pL", "\\P{gc=Lu}"
+fn parse_property(
+    chars: &mut Chars,
+    pos: usize,
+    c: char,
+    flags: Flags,
+) -> Result<CharClass, ParseError> {
+    let name = if chars.next_if(|(_, c)| *c == '{').is_some() {
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) => name.push(c),
+                // example: \p{Greek
+                None => return Err(ParseError::InvalidEscape(pos, c)),
+            }
+        }
+        name
+    } else if let Some((_, l)) = chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+        l.to_string()
+    } else {
+        // example: \p, \p1
+        return Err(ParseError::InvalidEscape(pos, c));
+    };
+
+    let mut class = match CharClass::property(&name) {
+        Some(class) => class,
+        None => return Err(ParseError::UnknownProperty(pos, name)),
+    };
+    if flags.case_insensitive {
+        class.case_fold();
+    }
+    if c == 'P' {
+        class.negate();
+    }
+    Ok(class)
+}
+
+/// convert the shorthand classes \d, \D, \w, \W, \s, \S into `CharClass`
+///
+/// return None if c is not one of them
+fn perl_class(c: char, flags: Flags) -> Option<CharClass> {
+    let mut class = match c.to_ascii_lowercase() {
+        'd' => CharClass::digit(flags.unicode),
+        'w' => CharClass::word(flags.unicode),
+        's' => CharClass::space(flags.unicode),
+        _ => return None,
+    };
+    if flags.case_insensitive {
+        class.case_fold();
+    }
+    if c.is_ascii_uppercase() {
+        class.negate();
+    }
+    Some(class)
+}
+
+/// element of a character class
+enum ClassItem {
+    Char(usize, char), // single char with its position
+    Class(CharClass),  // shorthand, POSIX or nested class, such as \d
+}
+
+/// parse a POSIX class such as `[:alpha:]` or its negation `[:^alpha:]` in a character class, just after `[`.
+///
+/// pos is the position of `[`.
+/// return None without consuming chars if `[` does not start a POSIX class but a nested class, such as "[[:a]]".
+fn parse_posix_class(
+    chars: &mut Chars,
+    pos: usize,
+    flags: Flags,
+) -> Result<Option<CharClass>, ParseError> {
+    let mut lookahead = chars.clone();
+    if lookahead.next_if(|(_, c)| *c == ':').is_none() {
+        return Ok(None);
+    }
+    let negated = lookahead.next_if(|(_, c)| *c == '^').is_some();
+
+    let mut name = String::new();
+    while let Some((_, c)) = lookahead.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+        name.push(c);
+    }
+    if lookahead.next_if(|(_, c)| *c == ':').is_none()
+        || lookahead.next_if(|(_, c)| *c == ']').is_none()
+    {
+        return Ok(None);
+    }
+    *chars = lookahead;
+
+    let mut class = match CharClass::posix(&name) {
+        Some(class) => class,
+        // example: [[:foo:]]
+        None => return Err(ParseError::UnknownPosixClass(pos, name)),
+    };
+    if flags.case_insensitive {
+        class.case_fold();
+    }
+    if negated {
+        class.negate();
+    }
+    Ok(Some(class))
+}
+
+/// parse one element of a character class, escaped or not.
+///
+/// return None at the `]` closing the class.
+fn parse_class_item(
+    chars: &mut Chars,
+    pos: usize,
+    flags: Flags,
+) -> Result<Option<ClassItem>, ParseError> {
+    match chars.next() {
+        Some((_, ']')) => Ok(None),
+        Some((i, '\\')) => match chars.next() {
+            Some((_, c)) if is_meta_char(c) || c.is_whitespace() => Ok(Some(ClassItem::Char(i, c))),
+            Some((j, c @ ('p' | 'P'))) => {
+                let class = parse_property(chars, j, c, flags)?;
+                Ok(Some(ClassItem::Class(class)))
+            }
+            Some((j, c)) => {
+                if let Some(c) = parse_char_escape(chars, j, c)? {
+                    Ok(Some(ClassItem::Char(i, c)))
+                } else if let Some(class) = perl_class(c, flags) {
+                    Ok(Some(ClassItem::Class(class)))
+                } else {
+                    Err(ParseError::InvalidEscape(j, c))
+                }
+            }
+            None => Err(ParseError::NoRightBracket(pos)),
+        },
+        // POSIX class or nested class
+        Some((i, '[')) => match parse_posix_class(chars, i, flags)? {
+            Some(class) => Ok(Some(ClassItem::Class(class))),
+            None => Ok(Some(ClassItem::Class(parse_class(chars, i, flags)?))),
+        },
+        Some((i, c)) => Ok(Some(ClassItem::Char(i, c))),
+        None => Err(ParseError::NoRightBracket(pos)),
+    }
+}
+
+/// set operation between character classes
+enum SetOp {
+    Intersection, // &&
+    Difference,   // --
+}
+
+/// the set operation at the head of chars, without consuming it
+fn peek_set_op(chars: &Chars) -> Option<SetOp> {
+    let mut lookahead = chars.clone();
+    match (lookahead.next(), lookahead.next()) {
+        (Some((_, '&')), Some((_, '&'))) => Some(SetOp::Intersection),
+        (Some((_, '-')), Some((_, '-'))) => Some(SetOp::Difference),
+        _ => None,
+    }
+}
+
+/// parse the union of elements in a character class, until `]` or a set operation.
+///
+/// pos is the position of `[`.
+/// return the union and the set operation after it, or None if it ends at the `]` closing the class.
+fn parse_class_union(
+    chars: &mut Chars,
+    pos: usize,
+    flags: Flags,
+) -> Result<(CharClass, Option<SetOp>), ParseError> {
+    let mut class = CharClass::new();
+    let mut literals = CharClass::new(); // chars and ranges, folded at the end
+    let mut empty = true;
+
+    let op = loop {
+        if let Some(op) = peek_set_op(chars) {
+            chars.nth(1);
+            break Some(op);
+        }
+
+        let item = match parse_class_item(chars, pos, flags)? {
+            Some(item) => item,
+            None => break None,
+        };
+        empty = false;
+
+        let (i, lo) = match item {
+            ClassItem::Char(i, lo) => (i, lo),
+            ClassItem::Class(c) => {
+                class.union(&c);
+                continue;
+            }
+        };
+
+        // '-' is a range only between two chars, otherwise it is a literal, such as [-a] and [a-],
+        // and "--" is a difference, such as [a--b]
+        let mut lookahead = chars.clone();
+        let is_range = matches!(lookahead.next(), Some((_, '-')))
+            && !matches!(lookahead.next(), Some((_, ']' | '-')) | None);
+        if is_range {
+            chars.next();
+            match parse_class_item(chars, pos, flags)? {
+                Some(ClassItem::Char(_, hi)) => {
+                    if lo > hi {
+                        return Err(ParseError::InvalidClassRange(i, lo, hi));
+                    }
+                    literals.push(lo, hi);
+                    continue;
+                }
+                // a shorthand class cannot end a range, so '-' is a literal, such as [a-\d]
+                Some(ClassItem::Class(c)) => {
+                    literals.push('-', '-');
+                    class.union(&c);
+                }
+                None => (),
+            }
+        }
+
+        literals.push(lo, lo);
+    };
+
+    if empty {
+        // example: [], [^], [a&&], [--a]
+        return Err(ParseError::EmptyClass(pos));
+    }
+
+    // shorthand, POSIX, property and nested classes are already folded, before their negation
+    if flags.case_insensitive {
+        literals.case_fold();
+    }
+    class.union(&literals);
+    Ok((class, op))
+}
+
+/// convert character class into `CharClass`, just after `[`.
+///
+/// pos is the position of `[`.
+/// classes can be nested, and combined by intersection `&&` and difference `--`,
+/// which are evaluated from left to right after the union of the elements between them.
+///
+/// example: "[abc]", "[a-z0-9]", "[^\\]-]", "[\\d_]", "[[:alpha:]_]", "[a-z&&[^x]]", "[\\p{L}--[aeiou]]"
+fn parse_class(chars: &mut Chars, pos: usize, flags: Flags) -> Result<CharClass, ParseError> {
+    let negated = chars.next_if(|(_, c)| *c == '^').is_some();
+    let (mut class, mut op) = parse_class_union(chars, pos, flags)?;
+
+    while let Some(set_op) = op {
+        let (other, next_op) = parse_class_union(chars, pos, flags)?;
+        match set_op {
+            SetOp::Intersection => class.intersect(&other),
+            SetOp::Difference => class.difference(&other),
+        }
+        op = next_op;
+    }
+
+    // the elements are folded before negation, so that (?i)[^a] matches neither 'a' nor 'A'
+    if negated {
+        class.negate();
+    }
+    Ok(class)
+}
+
 /// enum type for `parse_plus_star_question`
+#[allow(clippy::upper_case_acronyms)]
 enum PSQ {
     Plus,
     Star,
     Question,
+    Repeat(usize, Option<usize>),
 }
 
-/// convert +, *, ? into AST
+/// parse a decimal number in {n,m}.
+///
+/// return None if there is no digit.
+fn parse_number(chars: &mut Chars) -> Result<Option<usize>, ParseError> {
+    let mut n: Option<usize> = None;
+    while let Some((i, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        let digit = c as usize - '0' as usize;
+        let m = n
+            .unwrap_or(0)
+            .checked_mul(10)
+            .and_then(|m| m.checked_add(digit));
+        if m.is_none() {
+            // example: a{99999999999999999999}
+            return Err(ParseError::RepeatOverflow(i));
+        }
+        n = m;
+    }
+    Ok(n)
+}
+
+/// parse {n}, {n,} or {n,m} just after `{`.
+///
+/// pos is the position of `{`.
+fn parse_repeat(chars: &mut Chars, pos: usize) -> Result<PSQ, ParseError> {
+    let min = match parse_number(chars)? {
+        Some(n) => n,
+        // example: a{}, a{,3}
+        None => return Err(ParseError::InvalidRepeat(pos)),
+    };
+
+    let max = if chars.next_if(|(_, c)| *c == ',').is_some() {
+        parse_number(chars)?
+    } else {
+        Some(min)
+    };
+
+    match chars.next() {
+        Some((_, '}')) => match max {
+            // example: a{5,3}
+            Some(max) if min > max => Err(ParseError::InvalidRepeatRange(pos, min, max)),
+            _ => Ok(PSQ::Repeat(min, max)),
+        },
+        // example: a{3, a{3x}
+        _ => Err(ParseError::InvalidRepeat(pos)),
+    }
+}
+
+/// convert +, *, ?, {n,m} into AST
+///
+/// the quantifier is lazy if it is followed by `?`, such as "a*?",
+/// and possessive if it is followed by `+`, such as "a*+", which is the same as "(?>a*)".
 ///
 /// return `ParseError` if no pattern exists before PSQ
 ///
 /// example: "*ab", "abc|+" return error
 fn parse_plus_star_question(
     seq: &mut Vec<AST>,
+    chars: &mut Chars,
     ast_type: PSQ,
     pos: usize,
 ) -> Result<(), ParseError> {
     if let Some(prev) = seq.pop() {
+        let greedy = chars.next_if(|(_, c)| *c == '?').is_none();
+        let possessive = greedy && chars.next_if(|(_, c)| *c == '+').is_some();
         let ast = match ast_type {
-            PSQ::Plus => AST::Plus(Box::new(prev)),
-            PSQ::Star => AST::Star(Box::new(prev)),
-            PSQ::Question => AST::Question(Box::new(prev)),
+            PSQ::Plus => AST::Plus(Box::new(prev), greedy),
+            PSQ::Star => AST::Star(Box::new(prev), greedy),
+            PSQ::Question => AST::Question(Box::new(prev), greedy),
+            PSQ::Repeat(min, max) => AST::Repeat {
+                e: Box::new(prev),
+                min,
+                max,
+                greedy,
+            },
         };
-        seq.push(ast);
+        if possessive {
+            seq.push(AST::Atomic(Box::new(ast)));
+        } else {
+            seq.push(ast);
+        }
         Ok(())
     } else {
         Err(ParseError::NoPrev(pos))
@@ -94,21 +741,144 @@ fn parse_plus_star_question(
 /// convert some expression connected by Or into AST
 ///
 /// example: abs|def|ghi -> `AST::Or("abc", AST::Or("def", "ghi"))`
-fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
-    if seq_or.len() > 1 {
-        let mut ast = seq_or.pop().unwrap();
-        seq_or.reverse();
-        for s in seq_or {
-            ast = AST::Or(Box::new(s), Box::new(ast));
-        }
-        Some(ast)
-    } else {
-        seq_or.pop()
+///
+/// an empty alternative is an empty `AST::Seq`, which matches the empty string.
+fn fold_or(mut seq_or: Vec<AST>) -> AST {
+    let mut ast = seq_or.pop().unwrap_or(AST::Seq(Vec::new()));
+    while let Some(s) = seq_or.pop() {
+        ast = AST::Or(Box::new(s), Box::new(ast));
+    }
+    ast
+}
+
+/// parse the name of a named capture group, just after `<` of `(?P<name>` or `(?<name>`.
+///
+/// pos is the position of `<`.
+/// a name starts with a letter or `_`, followed by letters, digits or `_`, and ends with `>`.
+fn parse_group_name(chars: &mut Chars, pos: usize) -> Result<String, ParseError> {
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some((i, '>')) => {
+                if name.is_empty() {
+                    // example: (?<>a)
+                    return Err(ParseError::InvalidGroupName(i));
+                }
+                return Ok(name);
+            }
+            Some((i, c)) => {
+                let valid = if name.is_empty() {
+                    c == '_' || c.is_alphabetic()
+                } else {
+                    c == '_' || c.is_alphanumeric()
+                };
+                if !valid {
+                    // example: (?<1a>a), (?<a-b>a)
+                    return Err(ParseError::InvalidGroupName(i));
+                }
+                name.push(c);
+            }
+            // example: (?<abc
+            None => return Err(ParseError::InvalidGroupName(pos)),
+        }
+    }
+}
+
+/// kind of group, decided by the syntax just after `(`
+enum Group {
+    Capture(Option<String>), // (...), (?P<name>...), (?<name>...)
+    NonCapture(Flags),       // (?:...), (?flags:...), with the flags inside the group
+    SetFlags(Flags),         // (?flags), changes the flags until the end of the enclosing group
+    Look(Look),              // (?=...), (?!...), (?<=...), (?<!...)
+    Atomic,                  // (?>...)
+}
+
+/// what the expression inside a group becomes when the group is closed
+enum Wrap {
+    Capture(usize),    // AST::Capture with the group number
+    NonCapture,        // the expression itself
+    Look(Look, usize), // AST::Look, with the position of `(`
+    Atomic,            // AST::Atomic
+}
+
+/// parse flags such as `ims-x` in `(?ims-x)` or `(?ims-x:...)`, just after `(?`.
+///
+/// flags before `-` are set and flags after it are cleared.
+fn parse_flags(chars: &mut Chars, pos: usize, mut flags: Flags) -> Result<Group, ParseError> {
+    let mut yes = true;
+    let mut empty = true; // no flag since the start or `-`
+    loop {
+        let (i, c) = chars.next().ok_or(ParseError::UnknownGroup(pos))?;
+        match c {
+            'i' => flags.case_insensitive = yes,
+            'm' => flags.multi_line = yes,
+            's' => flags.dot_matches_new_line = yes,
+            'x' => flags.ignore_whitespace = yes,
+            '-' if yes => {
+                yes = false;
+                empty = true;
+                continue;
+            }
+            // example: (?i-), (?-:a)
+            ':' | ')' if !yes && empty => return Err(ParseError::InvalidFlag(i)),
+            ':' => return Ok(Group::NonCapture(flags)),
+            ')' => return Ok(Group::SetFlags(flags)),
+            // example: (?a), (?i-m-s)
+            _ => return Err(ParseError::InvalidFlag(i)),
+        }
+        empty = false;
+    }
+}
+
+/// parse the syntax just after `(?`.
+///
+/// pos is the position of `?`, and flags are the ones outside the group.
+fn parse_group_extension(chars: &mut Chars, pos: usize, flags: Flags) -> Result<Group, ParseError> {
+    if let Some((_, 'i' | 'm' | 's' | 'x' | '-')) = chars.peek() {
+        return parse_flags(chars, pos, flags);
+    }
+
+    match chars.next() {
+        Some((_, ':')) => Ok(Group::NonCapture(flags)),
+        Some((_, '=')) => Ok(Group::Look(Look::Ahead)),
+        Some((_, '!')) => Ok(Group::Look(Look::NegAhead)),
+        Some((_, '>')) => Ok(Group::Atomic),
+        Some((_, '<')) if chars.next_if(|(_, c)| *c == '=').is_some() => {
+            Ok(Group::Look(Look::Behind))
+        }
+        Some((_, '<')) if chars.next_if(|(_, c)| *c == '!').is_some() => {
+            Ok(Group::Look(Look::NegBehind))
+        }
+        Some((_, 'P')) => match chars.next() {
+            Some((i, '<')) => Ok(Group::Capture(Some(parse_group_name(chars, i)?))),
+            Some((i, _)) => Err(ParseError::UnknownGroup(i)),
+            None => Err(ParseError::UnknownGroup(pos)),
+        },
+        Some((i, '<')) => Ok(Group::Capture(Some(parse_group_name(chars, i)?))),
+        Some((i, _)) => Err(ParseError::UnknownGroup(i)),
+        None => Err(ParseError::UnknownGroup(pos)),
+    }
+}
+
+/// skip white spaces and `#` comments if ignore_whitespace is set
+fn skip_ignored(chars: &mut Chars, flags: Flags) {
+    if !flags.ignore_whitespace {
+        return;
+    }
+    loop {
+        if chars.next_if(|(_, c)| c.is_whitespace()).is_some() {
+            continue;
+        }
+        if chars.next_if(|(_, c)| *c == '#').is_some() {
+            while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            continue;
+        }
+        break;
     }
 }
 
 /// convert regular expression intp AST
-pub fn parse(expr: &str) -> Result<AST, ParseError> {
+pub fn parse(expr: &str, mut flags: Flags) -> Result<Parsed, ParseError> {
     // Char:    processing string
     // Escape:  processing escape sequence
     enum ParseState {
@@ -118,30 +888,83 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
 
     let mut seq = Vec::new(); // current Seq context
     let mut seq_or = Vec::new(); // current Or context
-    let mut stack = Vec::new(); // context stack
+    let mut stack = Vec::new(); // context stack, with the flags outside each group
+    let mut names = vec![None]; // name of each capture group
     let mut state = ParseState::Char; // current state
 
-    for (i, c) in expr.chars().enumerate() {
+    let mut chars = expr.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
         match &state {
+            ParseState::Char if flags.ignore_whitespace && c.is_whitespace() => (),
+            ParseState::Char if flags.ignore_whitespace && c == '#' => {
+                // comment until the end of line
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            }
             ParseState::Char => match c {
-                '+' => parse_plus_star_question(&mut seq, PSQ::Plus, i)?,
-                '*' => parse_plus_star_question(&mut seq, PSQ::Star, i)?,
-                '?' => parse_plus_star_question(&mut seq, PSQ::Question, i)?,
+                '+' => parse_plus_star_question(&mut seq, &mut chars, PSQ::Plus, i)?,
+                '*' => parse_plus_star_question(&mut seq, &mut chars, PSQ::Star, i)?,
+                '?' => parse_plus_star_question(&mut seq, &mut chars, PSQ::Question, i)?,
+                '{' => {
+                    let repeat = parse_repeat(&mut chars, i)?;
+                    parse_plus_star_question(&mut seq, &mut chars, repeat, i)?
+                }
                 '(' => {
+                    let group = if let Some((pos, _)) = chars.next_if(|(_, c)| *c == '?') {
+                        parse_group_extension(&mut chars, pos, flags)?
+                    } else {
+                        Group::Capture(None)
+                    };
+
+                    // number the capture group, non-capturing group has no number
+                    let (wrap, inner) = match group {
+                        Group::Capture(Some(name)) if names.contains(&Some(name.clone())) => {
+                            return Err(ParseError::DuplicateGroupName(i, name));
+                        }
+                        Group::Capture(name) => {
+                            names.push(name);
+                            (Wrap::Capture(names.len() - 1), flags)
+                        }
+                        Group::NonCapture(inner) => (Wrap::NonCapture, inner),
+                        Group::Look(look) => (Wrap::Look(look, i), flags),
+                        Group::Atomic => (Wrap::Atomic, flags),
+                        Group::SetFlags(new_flags) => {
+                            // flags cannot be repeated (ex: a(?i)*, a(?x) *)
+                            skip_ignored(&mut chars, new_flags);
+                            let is_quantifier = |(_, c): &(usize, char)| "+*?{".contains(*c);
+                            if let Some((pos, _)) = chars.next_if(is_quantifier) {
+                                return Err(ParseError::NoPrev(pos));
+                            }
+                            flags = new_flags;
+                            continue;
+                        }
+                    };
+
                     // save current context in stack
                     // and make current context empty
                     let prev = take(&mut seq);
                     let prev_or = take(&mut seq_or);
-                    stack.push((prev, prev_or));
+                    stack.push((prev, prev_or, wrap, flags));
+                    flags = inner;
                 }
                 ')' => {
-                    if let Some((mut prev, prev_or)) = stack.pop() {
-                        // if exp is empty (ex: "()"), does not push
-                        if !seq.is_empty() {
-                            seq_or.push(AST::Seq(seq));
-                        }
-                        if let Some(ast) = fold_or(seq_or) {
-                            prev.push(ast);
+                    if let Some((mut prev, prev_or, wrap, outer)) = stack.pop() {
+                        flags = outer;
+                        // empty group (ex: "()", "(a|)") matches the empty string
+                        seq_or.push(AST::Seq(seq));
+                        let ast = fold_or(seq_or);
+                        match wrap {
+                            Wrap::Capture(index) => prev.push(AST::Capture(index, Box::new(ast))),
+                            Wrap::NonCapture => prev.push(ast),
+                            Wrap::Atomic => prev.push(AST::Atomic(Box::new(ast))),
+                            Wrap::Look(look, pos) => {
+                                if matches!(look, Look::Behind | Look::NegBehind)
+                                    && ast.length().1.is_none()
+                                {
+                                    // example: (?<=a+), (?<!a|b*)
+                                    return Err(ParseError::UnboundedLookBehind(pos));
+                                }
+                                prev.push(AST::Look(look, Box::new(ast)));
+                            }
                         }
                         // update context
                         seq = prev;
@@ -152,38 +975,40 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
                     }
                 }
                 '|' => {
-                    if seq.is_empty() {
-                        // example: "||", "(|abd)"
-                        return Err(ParseError::NoPrev(i));
-                    } else {
-                        let prev = take(&mut seq);
-                        seq_or.push(AST::Seq(prev));
-                    }
+                    // empty alternative (ex: "|b", "a||b") matches the empty string
+                    let prev = take(&mut seq);
+                    seq_or.push(AST::Seq(prev));
                 }
+                '[' => seq.push(AST::Class(parse_class(&mut chars, i, flags)?)),
+                '.' => seq.push(AST::Any(flags.dot_matches_new_line)),
+                '^' if flags.multi_line => seq.push(AST::Assert(Assertion::StartLine)),
+                '^' => seq.push(AST::Assert(Assertion::StartText)),
+                '$' if flags.multi_line => seq.push(AST::Assert(Assertion::EndLine)),
+                '$' => seq.push(AST::Assert(Assertion::EndText)),
                 '\\' => state = ParseState::Escape,
-                _ => seq.push(AST::Char(c)),
+                _ => seq.push(literal(c, flags)),
             },
             ParseState::Escape => {
-                let ast = parse_escape(i, c)?;
+                let ast = parse_escape(&mut chars, i, c, flags, &names)?;
                 seq.push(ast);
                 state = ParseState::Char;
             }
         }
     }
 
+    if let ParseState::Escape = state {
+        // example: abc\
+        return Err(ParseError::InvalidEscape(expr.chars().count() - 1, '\\'));
+    }
+
     if !stack.is_empty() {
         return Err(ParseError::NoRightParen);
     }
 
-    // commit current seq unless it's not empty
-    if !seq.is_empty() {
-        seq_or.push(AST::Seq(seq));
-    }
+    // commit current seq, which is empty for "" and "a|"
+    seq_or.push(AST::Seq(seq));
 
     // nanka iikanji ni naruppoi
-    if let Some(ast) = fold_or(seq_or) {
-        Ok(ast)
-    } else {
-        Err(ParseError::Empty)
-    }
+    let ast = fold_or(seq_or);
+    Ok(Parsed { ast, names })
 }
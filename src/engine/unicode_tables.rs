@@ -0,0 +1,12 @@
+//! Unicode property tables embedded in the crate
+//!
+//! each table is sorted by char or by name, generated from the Unicode Character Database.
+//! see LICENSE-UNICODE in unicode_tables/ for the terms of use of the data.
+
+pub mod case_folding_simple;
+pub mod general_category;
+pub mod perl_decimal;
+pub mod perl_space;
+pub mod perl_word;
+pub mod property_values;
+pub mod script;
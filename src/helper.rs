@@ -22,4 +22,24 @@ where
 	}
 }
 
-pub type DynError = Box<dyn std::error::Error + Send + Sync + 'static>;
\ No newline at end of file
+pub type DynError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+#[cfg(test)]
+mod tests {
+    use super::{safe_add, SafeAdd};
+
+    #[test]
+    fn test_safe_add() {
+        let n = 10usize;
+        assert_eq!(Some(30), n.safe_add(&20));
+
+        let n = !0usize;
+        assert_eq!(None, n.safe_add(&1));
+
+        let mut n = 10usize;
+        assert!(safe_add(&mut n, &20, || ()).is_ok());
+
+        let mut n = !0usize;
+        assert!(safe_add(&mut n, &1, || ()).is_err());
+    }
+}
@@ -3,12 +3,20 @@
 //! ## example usage
 //! ```
 //! use myregex;
-//! let expr = "a(bc)+|c(def)*"; 				// regular expression
-//! let line = "cdefdefdef"; 					// string to match
-//! myregex::do_matching(expr, line, true);		// match by dfs
-//! myregex::print(expr);						// print AST of regular expression and sequence of instructions
+//! let expr = "a(bc)+|c(def)*";             // regular expression
+//! let line = "cdefdefdef";                 // string to match
+//! myregex::do_matching(expr, line, true);  // match by dfs
+//! myregex::print(expr);                    // print AST of regular expression and sequence of instructions
+//!
+//! // compile once and match many times
+//! let re = myregex::Regex::new(expr).unwrap();
+//! assert!(re.is_match(line).unwrap());
 //! ```
 mod engine;
 mod helper;
 
-pub use engine::{do_matching, print};
+pub use engine::{
+    do_matching, escape, find, print, Captures, CodeGenError, Match, MatchKind, ParseError, Regex,
+    RegexBuilder,
+};
+pub use helper::DynError;
@@ -1,7 +1,4 @@
-mod engine;
-mod helper;
-
-use helper::DynError;
+use myregex::{DynError, MatchKind, RegexBuilder};
 use std::{
     env,
     fs::File,
@@ -9,41 +6,47 @@ use std::{
 };
 
 fn main() -> Result<(), DynError> {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        eprintln!("usage: {} regex file", args[0]);
+    let mut args = env::args().collect::<Vec<String>>();
+
+    // option to choose where a match may start and end in each line
+    let kind = match args.get(1).map(|arg| arg.as_str()) {
+        Some("--prefix") => Some(MatchKind::Prefix),
+        Some("--full") => Some(MatchKind::Full),
+        Some("--search") => Some(MatchKind::Search),
+        _ => None,
+    };
+    if kind.is_some() {
+        args.remove(1);
+    }
+
+    if args.len() < 3 {
+        eprintln!("usage: {} [--prefix|--full|--search] regex file", args[0]);
         return Err("invalid arguments".into());
     } else {
-        match_file(&args[1], &args[2])?;
+        match_file(&args[1], &args[2], kind.unwrap_or(MatchKind::Search))?;
     }
 
     Ok(())
 }
 
-/// match by shifting one character from the beginning of each line,
-/// and considered matched if it matches any of the shift.
-///
-/// for example, if there is a string "abcd", matching is done in the following order,
-/// and if any of these matches, the line is considered to match the given regular expression.
+/// print every line of file that matches expr.
 ///
-/// - abcd
-/// - bcd
-/// - cd
-/// - d
-fn match_file(expr: &str, file: &str) -> Result<(), DynError> {
+/// the expression is compiled once, and each line is matched in a single pass.
+/// with `MatchKind::Search` a match may start at any position of the line,
+/// with `MatchKind::Prefix` it must start at the beginning of the line,
+/// and with `MatchKind::Full` it must cover the whole line.
+fn match_file(expr: &str, file: &str, kind: MatchKind) -> Result<(), DynError> {
     let f = File::open(file)?;
     let reader = BufReader::new(f);
 
-    engine::print(expr)?;
+    myregex::print(expr)?;
     println!();
 
+    let re = RegexBuilder::new(expr).match_kind(kind).build()?;
     for line in reader.lines() {
         let line = line?;
-        for (i, _) in line.char_indices() {
-            if engine::do_matching(expr, &line[i..], true)? {
-                println!("{line}");
-                break;
-            }
+        if re.is_match(&line)? {
+            println!("{line}");
         }
     }
 
@@ -52,24 +55,39 @@ fn match_file(expr: &str, file: &str) -> Result<(), DynError> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{
-        engine::do_matching,
-        helper::{safe_add, SafeAdd},
+    use myregex::{
+        do_matching, escape, find, CodeGenError, MatchKind, ParseError, Regex, RegexBuilder,
     };
 
-    #[test]
-    fn test_safe_add() {
-        let n = 10usize;
-        assert_eq!(Some(30), n.safe_add(&20));
-
-        let n = !0usize;
-        assert_eq!(None, n.safe_add(&1));
+    /// find expr in line and return the byte span of the match
+    fn span(expr: &str, line: &str, use_dfs: bool) -> Option<(usize, usize)> {
+        find(expr, line, use_dfs)
+            .unwrap()
+            .map(|m| (m.start(), m.end()))
+    }
 
-        let mut n = 10usize;
-        assert!(safe_add(&mut n, &20, || ()).is_ok());
+    /// same as span, but with Unicode support disabled
+    fn ascii(expr: &str, line: &str, use_dfs: bool) -> Option<(usize, usize)> {
+        RegexBuilder::new(expr)
+            .use_dfs(use_dfs)
+            .unicode(false)
+            .build()
+            .unwrap()
+            .find(line)
+            .unwrap()
+            .map(|m| (m.start(), m.end()))
+    }
 
-        let mut n = !0usize;
-        assert!(safe_add(&mut n, &1, || ()).is_err());
+    /// same as span, but case insensitive
+    fn fold(expr: &str, line: &str, use_dfs: bool) -> Option<(usize, usize)> {
+        RegexBuilder::new(expr)
+            .use_dfs(use_dfs)
+            .case_insensitive(true)
+            .build()
+            .unwrap()
+            .find(line)
+            .unwrap()
+            .map(|m| (m.start(), m.end()))
     }
 
     #[test]
@@ -78,7 +96,6 @@ mod tests {
             // parse error
             assert!(do_matching("+b", "bbb", use_dfs).is_err());
             assert!(do_matching("*b", "bbb", use_dfs).is_err());
-            assert!(do_matching("|b", "bbb", use_dfs).is_err());
             assert!(do_matching("?b", "bbb", use_dfs).is_err());
 
             // parse ok, match success
@@ -96,4 +113,1033 @@ mod tests {
             assert!(!do_matching("abc?", "acb", use_dfs).unwrap());
         }
     }
+
+    #[test]
+    fn test_regex() {
+        for use_dfs in [true, false] {
+            let re = RegexBuilder::new("(ab|cd)+e")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            assert!(re.is_match("abcde").unwrap());
+            assert!(re.is_match("cdcdabe").unwrap());
+            assert!(!re.is_match("abcd").unwrap());
+            assert!(!re.is_match("").unwrap());
+        }
+
+        assert!(Regex::new("a|*").is_err());
+        assert!(Regex::new("(abc").is_err());
+    }
+
+    #[test]
+    fn test_search() {
+        for use_dfs in [true, false] {
+            // match may start anywhere in the line
+            assert!(do_matching("abc", "xxabcxx", use_dfs).unwrap());
+            assert!(do_matching("b+c", "aaabbbc", use_dfs).unwrap());
+            assert!(do_matching("(ab|cd)+", "xxcdab", use_dfs).unwrap());
+            assert!(do_matching("a*", "", use_dfs).unwrap());
+            assert!(do_matching("x?", "abc", use_dfs).unwrap());
+            assert!(do_matching("あい", "おあい", use_dfs).unwrap());
+
+            assert!(!do_matching("abc", "abxabyabz", use_dfs).unwrap());
+            assert!(!do_matching("ba", "aaaaaaaaa", use_dfs).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_match_kind() {
+        for use_dfs in [true, false] {
+            let build = |expr: &str, kind| {
+                RegexBuilder::new(expr)
+                    .use_dfs(use_dfs)
+                    .match_kind(kind)
+                    .build()
+                    .unwrap()
+            };
+
+            let re = build("abc?", MatchKind::Prefix);
+            assert!(re.is_match("abxyz").unwrap());
+            assert!(re.is_match("abc").unwrap());
+            assert!(!re.is_match("xabc").unwrap());
+
+            let re = build("abc?", MatchKind::Full);
+            assert!(re.is_match("ab").unwrap());
+            assert!(re.is_match("abc").unwrap());
+            assert!(!re.is_match("abxyz").unwrap());
+            assert!(!re.is_match("xabc").unwrap());
+            assert!(!re.is_match("abcc").unwrap());
+
+            // backtrack until the whole input is consumed
+            let re = build("(a|ab)(c|bcd)", MatchKind::Full);
+            assert!(re.is_match("abcd").unwrap());
+            let re = build("a*", MatchKind::Full);
+            assert!(re.is_match("").unwrap());
+            assert!(re.is_match("aaaa").unwrap());
+            assert!(!re.is_match("aaba").unwrap());
+
+            let re = build("abc?", MatchKind::Search);
+            assert!(re.is_match("xxabxyz").unwrap());
+            assert!(!re.is_match("axbc").unwrap());
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("abc", "xxabcxx", use_dfs), Some((2, 5)));
+            assert_eq!(span("(ab|cd)+", "xxcdabab", use_dfs), Some((2, 8)));
+            assert_eq!(span("a*", "bbaa", use_dfs), Some((0, 0)));
+            assert_eq!(span("a+", "bbaa", use_dfs), Some((2, 4)));
+            assert_eq!(span("abc?", "abab", use_dfs), Some((0, 2)));
+            assert_eq!(span("b|ab", "xab", use_dfs), Some((1, 3)));
+            assert_eq!(span("xyz", "xyxy", use_dfs), None);
+
+            // leftmost-first: the first alternative wins even if another is longer
+            assert_eq!(span("a|ab", "ab", use_dfs), Some((0, 1)));
+            assert_eq!(span("(a|ab)(c|bcd)", "abcd", use_dfs), Some((0, 4)));
+
+            // byte offsets and char offsets differ for multibyte chars
+            let m = find("い+", "あいいう", use_dfs).unwrap().unwrap();
+            assert_eq!(m.range(), 3..9);
+            assert_eq!(m.char_range(), 1..3);
+            assert_eq!(m.as_str(), "いい");
+
+            let re = RegexBuilder::new("a+")
+                .use_dfs(use_dfs)
+                .match_kind(MatchKind::Full)
+                .build()
+                .unwrap();
+            assert_eq!(re.find("aaa").unwrap().map(|m| m.range()), Some(0..3));
+            assert!(re.find("aab").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_captures() {
+        for use_dfs in [true, false] {
+            let re = RegexBuilder::new("(a+)(b|(c))d")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            assert_eq!(re.captures_len(), 4);
+
+            let caps = re.captures("xaabdy").unwrap().unwrap();
+            assert_eq!(caps.len(), 4);
+            assert_eq!(&caps[0], "aabd");
+            assert_eq!(&caps[1], "aa");
+            assert_eq!(&caps[2], "b");
+            assert!(caps.get(3).is_none());
+            assert!(caps.get(4).is_none());
+
+            let caps = re.captures("acd").unwrap().unwrap();
+            assert_eq!(caps.get(2).unwrap().range(), 1..2);
+            assert_eq!(caps.get(3).unwrap().range(), 1..2);
+
+            assert!(re.captures("abc").unwrap().is_none());
+
+            // a group in a loop captures its last iteration
+            let re = RegexBuilder::new("(ab|cd)+")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            let caps = re.captures("abcdab").unwrap().unwrap();
+            assert_eq!(caps.get(1).unwrap().range(), 4..6);
+
+            // nested groups are numbered by their left parenthesis
+            let re = RegexBuilder::new("((あ)(い+))う")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            let caps = re.captures("ああいいう").unwrap().unwrap();
+            assert_eq!(&caps[1], "あいい");
+            assert_eq!(&caps[2], "あ");
+            assert_eq!(&caps[3], "いい");
+            assert_eq!(caps.get(3).unwrap().char_range(), 2..4);
+
+            // groups that can match empty inside a loop
+            let re = RegexBuilder::new("(a*)*b")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            let caps = re.captures("aab").unwrap().unwrap();
+            assert_eq!(&caps[0], "aab");
+            assert_eq!(&caps[1], "aa");
+        }
+    }
+
+    /// parse expr and return the ParseError it fails with
+    fn parse_error(expr: &str) -> ParseError {
+        let err = Regex::new(expr).unwrap_err();
+        *err.downcast::<ParseError>().unwrap()
+    }
+
+    #[test]
+    fn test_named_captures() {
+        for use_dfs in [true, false] {
+            let re = RegexBuilder::new("(?P<first>a+)(b)(?<third_3>c|d)")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            let names = re.capture_names().collect::<Vec<_>>();
+            assert_eq!(names, vec![None, Some("first"), None, Some("third_3")]);
+
+            let caps = re.captures("xaabdy").unwrap().unwrap();
+            assert_eq!(&caps["first"], "aa");
+            assert_eq!(&caps[2], "b");
+            assert_eq!(&caps["third_3"], "d");
+            assert_eq!(caps.name("third_3"), caps.get(3));
+            assert!(caps.name("second").is_none());
+        }
+
+        assert_eq!(
+            parse_error("(?P<a>x)(?<a>y)"),
+            ParseError::DuplicateGroupName(8, "a".to_string())
+        );
+        assert_eq!(parse_error("(?<>x)"), ParseError::InvalidGroupName(3));
+        assert_eq!(parse_error("(?<1a>x)"), ParseError::InvalidGroupName(3));
+        assert_eq!(parse_error("(?P<a-b>x)"), ParseError::InvalidGroupName(5));
+        assert_eq!(parse_error("a(?<abc"), ParseError::InvalidGroupName(3));
+        assert_eq!(parse_error("(?Px>a)"), ParseError::UnknownGroup(3));
+        assert_eq!(parse_error("(?"), ParseError::UnknownGroup(1));
+    }
+
+    #[test]
+    fn test_non_capturing_group() {
+        for use_dfs in [true, false] {
+            let re = RegexBuilder::new("(?:ab|cd)+(e)(?:f(g))?")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            assert_eq!(re.captures_len(), 3);
+
+            let caps = re.captures("xabcdefg").unwrap().unwrap();
+            assert_eq!(&caps[0], "abcdefg");
+            assert_eq!(&caps[1], "e");
+            assert_eq!(&caps[2], "g");
+
+            assert!(do_matching("(?:(?:a)*)*b", "aab", use_dfs).unwrap());
+            assert!(!do_matching("(?:ab)+", "aacbb", use_dfs).unwrap());
+        }
+
+        assert_eq!(parse_error("(?;a)"), ParseError::UnknownGroup(2));
+    }
+
+    #[test]
+    fn test_class() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("[0-9]+", "abc2024def", use_dfs), Some((3, 7)));
+            assert_eq!(span("[a-cx]+", "zzcaxbyy", use_dfs), Some((2, 6)));
+            assert_eq!(span("[^a-z]+", "abcDEFghi", use_dfs), Some((3, 6)));
+            assert_eq!(span("[^abc]", "abc", use_dfs), None);
+            assert_eq!(span("[ぁ-ん]+", "カタかなカナ", use_dfs), Some((6, 12)));
+
+            // '-' at either end is a literal, and escaped chars
+            assert_eq!(span("[-a]+", "x-a-y", use_dfs), Some((1, 4)));
+            assert_eq!(span("[a-]+", "x-a-y", use_dfs), Some((1, 4)));
+            assert_eq!(span("[\\]\\-]+", "a]-]b", use_dfs), Some((1, 4)));
+            assert_eq!(span("[\\^x]+", "a^xb", use_dfs), Some((1, 3)));
+            assert_eq!(span("[\\\\]", "a\\b", use_dfs), Some((1, 2)));
+            assert_eq!(span("\\[a\\]", "x[a]", use_dfs), Some((1, 4)));
+
+            // class inside groups and repetition
+            assert_eq!(span("([a-c][0-9])+", "a1b2c3d4", use_dfs), Some((0, 6)));
+        }
+
+        assert_eq!(parse_error("[abc"), ParseError::NoRightBracket(0));
+        assert_eq!(parse_error("x[a\\]"), ParseError::NoRightBracket(1));
+        assert_eq!(parse_error("[]"), ParseError::EmptyClass(0));
+        assert_eq!(parse_error("a[^]"), ParseError::EmptyClass(1));
+        assert_eq!(
+            parse_error("[az-a]"),
+            ParseError::InvalidClassRange(2, 'z', 'a')
+        );
+        assert_eq!(parse_error("[\\q]"), ParseError::InvalidEscape(2, 'q'));
+    }
+
+    #[test]
+    fn test_any() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("a.c", "xxabcxx", use_dfs), Some((2, 5)));
+            assert_eq!(span("a.c", "a.c", use_dfs), Some((0, 3)));
+            assert_eq!(span("a.+", "xaあいう", use_dfs), Some((1, 11)));
+            assert_eq!(span("a.c", "ac", use_dfs), None);
+            assert_eq!(span("a\\.c", "abca.c", use_dfs), Some((3, 6)));
+            assert_eq!(span("[.]", "ab.c", use_dfs), Some((2, 3)));
+
+            // '\n' is not matched by default
+            assert_eq!(span("a.*", "ab\ncd", use_dfs), Some((0, 2)));
+            assert_eq!(span("a.c", "a\nc", use_dfs), None);
+
+            let re = RegexBuilder::new("a.*")
+                .use_dfs(use_dfs)
+                .dot_matches_new_line(true)
+                .build()
+                .unwrap();
+            assert_eq!(re.find("ab\ncd").unwrap().unwrap().range(), 0..5);
+        }
+    }
+
+    #[test]
+    fn test_anchor() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("^ERROR", "ERROR: disk full", use_dfs), Some((0, 5)));
+            assert_eq!(span("^ERROR", "no ERROR here", use_dfs), None);
+            assert_eq!(span("full$", "ERROR: disk full", use_dfs), Some((12, 16)));
+            assert_eq!(span("full$", "full disk", use_dfs), None);
+            assert_eq!(span("^$", "", use_dfs), Some((0, 0)));
+            assert_eq!(span("^a*$", "aaa", use_dfs), Some((0, 3)));
+            assert_eq!(span("^a*$", "aaab", use_dfs), None);
+            assert_eq!(span("\\Aab|b\\z", "abab", use_dfs), Some((0, 2)));
+            assert_eq!(span("c|b\\z", "abab", use_dfs), Some((3, 4)));
+            assert_eq!(span("(^|x)b", "ab xb", use_dfs), Some((3, 5)));
+            assert_eq!(span("\\^\\$", "a^$", use_dfs), Some((1, 3)));
+
+            // without multi line mode, ^ and $ ignore '\n'
+            assert_eq!(span("^b", "a\nb", use_dfs), None);
+            assert_eq!(span("a$", "a\nb", use_dfs), None);
+
+            let re = RegexBuilder::new("^b+$")
+                .use_dfs(use_dfs)
+                .multi_line(true)
+                .build()
+                .unwrap();
+            assert_eq!(re.find("a\nbb\nc").unwrap().unwrap().range(), 2..4);
+            assert!(re.find("a\nbc").unwrap().is_none());
+
+            let re = RegexBuilder::new("\\Ab")
+                .use_dfs(use_dfs)
+                .multi_line(true)
+                .build()
+                .unwrap();
+            assert!(re.find("a\nb").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_repeat() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("a{3}", "aaaaa", use_dfs), Some((0, 3)));
+            assert_eq!(span("a{3}", "aabaa", use_dfs), None);
+            assert_eq!(span("a{2,}", "baaaaab", use_dfs), Some((1, 6)));
+            assert_eq!(span("a{2,}", "bab", use_dfs), None);
+            assert_eq!(span("a{2,4}", "aaaaaa", use_dfs), Some((0, 4)));
+            assert_eq!(span("a{2,4}", "baaab", use_dfs), Some((1, 4)));
+            assert_eq!(span("ba{0,2}c", "bc", use_dfs), Some((0, 2)));
+            assert_eq!(span("ba{0}c", "bac bc", use_dfs), Some((4, 6)));
+            assert_eq!(span("(ab|c){2}d", "abcd", use_dfs), Some((0, 4)));
+            assert_eq!(
+                span("[0-9]{4}-[0-9]{2}", "on 2024-10-17", use_dfs),
+                Some((3, 10))
+            );
+            assert_eq!(span("a{1,3}a{2}", "aaa", use_dfs), Some((0, 3)));
+            assert_eq!(span("(a*){2,3}b", "aab", use_dfs), Some((0, 3)));
+            assert_eq!(span("a\\{2\\}", "aa a{2}", use_dfs), Some((3, 7)));
+        }
+
+        let re = Regex::new("(a|b){2,3}").unwrap();
+        let caps = re.captures("abba").unwrap().unwrap();
+        assert_eq!(&caps[0], "abb");
+        assert_eq!(caps.get(1).unwrap().range(), 2..3);
+
+        assert_eq!(parse_error("{3}"), ParseError::NoPrev(0));
+        assert_eq!(
+            parse_error("a{5,3}"),
+            ParseError::InvalidRepeatRange(1, 5, 3)
+        );
+        assert_eq!(parse_error("a{}"), ParseError::InvalidRepeat(1));
+        assert_eq!(parse_error("a{,3}"), ParseError::InvalidRepeat(1));
+        assert_eq!(parse_error("a{3"), ParseError::InvalidRepeat(1));
+        assert_eq!(parse_error("a{3,x}"), ParseError::InvalidRepeat(1));
+        assert_eq!(
+            parse_error("a{99999999999999999999999}"),
+            ParseError::RepeatOverflow(21)
+        );
+
+        // the expanded program is limited in size
+        let err = Regex::new("(a{1000}){1000}").unwrap_err();
+        assert_eq!(
+            *err.downcast::<CodeGenError>().unwrap(),
+            CodeGenError::TooLarge(100_000)
+        );
+        let err = RegexBuilder::new("a{10}")
+            .size_limit(10)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            *err.downcast::<CodeGenError>().unwrap(),
+            CodeGenError::TooLarge(10)
+        );
+        assert!(RegexBuilder::new("a{9}").size_limit(10).build().is_ok());
+    }
+
+    #[test]
+    fn test_lazy() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("a+?", "aaa", use_dfs), Some((0, 1)));
+            assert_eq!(span("a*?", "aaa", use_dfs), Some((0, 0)));
+            assert_eq!(span("a??", "aaa", use_dfs), Some((0, 0)));
+            assert_eq!(span("a{2,4}?", "aaaa", use_dfs), Some((0, 2)));
+            assert_eq!(span("a{2,}?", "aaaa", use_dfs), Some((0, 2)));
+            assert_eq!(span("<.+?>", "<a><b>", use_dfs), Some((0, 3)));
+            assert_eq!(span("<.+>", "<a><b>", use_dfs), Some((0, 6)));
+            assert_eq!(span("a*?b", "aaab", use_dfs), Some((0, 4)));
+            assert_eq!(span("(a*?)*b", "aab", use_dfs), Some((0, 3)));
+            assert_eq!(span("a??b", "ab", use_dfs), Some((0, 2)));
+        }
+
+        // depth-first and width-first search find the same leftmost-first match and captures
+        let exprs = [
+            "(a|ab)(c|bcd)(d*)",
+            "(a+?)(a*)",
+            "(a*)(a*?)b",
+            "(a??)(a+)",
+            "(<.*?>)(.*)",
+            "(a|b)*?(b+)",
+            "((ab)|(a))+(b*)",
+            "(x?)*y",
+            "(a{1,3}?)(a{0,2})$",
+        ];
+        let lines = ["abcd", "aaab", "<a><b>c", "ababbb", "xxy", "aaaa", "aab"];
+        for expr in exprs {
+            let dfs = RegexBuilder::new(expr).use_dfs(true).build().unwrap();
+            let bfs = RegexBuilder::new(expr).use_dfs(false).build().unwrap();
+            for line in lines {
+                assert_eq!(
+                    dfs.captures(line).unwrap(),
+                    bfs.captures(line).unwrap(),
+                    "expr = {expr}, line = {line}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_perl_class() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("\\d+", "abc2024def", use_dfs), Some((3, 7)));
+            assert_eq!(span("\\D+", "123abc456", use_dfs), Some((3, 6)));
+            assert_eq!(span("\\w+", "  foo_1 bar", use_dfs), Some((2, 7)));
+            assert_eq!(span("\\W+", "foo, bar", use_dfs), Some((3, 5)));
+            assert_eq!(span("\\s+", "foo \t\nbar", use_dfs), Some((3, 6)));
+            assert_eq!(span("\\S+", "  foo  ", use_dfs), Some((2, 5)));
+            assert_eq!(span("[\\d_]+", "ab_12c", use_dfs), Some((2, 5)));
+            assert_eq!(span("[^\\s]+", "  ab ", use_dfs), Some((2, 4)));
+            assert_eq!(span("[\\D]", "123a", use_dfs), Some((3, 4)));
+            assert_eq!(span("[a-\\d]+", "x-a1", use_dfs), Some((1, 4)));
+            assert_eq!(
+                span("(\\w+)@(\\w+)", "mail: foo@example", use_dfs),
+                Some((6, 17))
+            );
+
+            // Unicode aware by default
+            assert_eq!(span("\\d+", "value: ١٢٣", use_dfs), Some((7, 13)));
+            assert_eq!(span("\\w+", "¿qué?", use_dfs), Some((2, 6)));
+            assert_eq!(span("\\s", "a\u{3000}b", use_dfs), Some((1, 4)));
+
+            assert_eq!(ascii("\\d+", "value: ١٢٣", use_dfs), None);
+            assert_eq!(ascii("\\w+", "¿qué?", use_dfs), Some((2, 4)));
+            assert_eq!(ascii("\\s", "a\u{3000}b", use_dfs), None);
+            assert_eq!(ascii("\\W", "qué", use_dfs), Some((2, 4)));
+        }
+
+        assert_eq!(parse_error("\\q"), ParseError::InvalidEscape(1, 'q'));
+        assert_eq!(parse_error("[\\q]"), ParseError::InvalidEscape(2, 'q'));
+    }
+
+    #[test]
+    fn test_char_escape() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("a\\tb", "xa\tb", use_dfs), Some((1, 4)));
+            assert_eq!(span("\\r\\n", "line\r\n", use_dfs), Some((4, 6)));
+            assert_eq!(span("\\f\\v\\a", "\x0C\x0B\x07", use_dfs), Some((0, 3)));
+            assert_eq!(span("\\x41+", "xAAy", use_dfs), Some((1, 3)));
+            assert_eq!(span("\\x{e9}", "caf\u{e9}", use_dfs), Some((3, 5)));
+            assert_eq!(span("\\u00e9", "caf\u{e9}", use_dfs), Some((3, 5)));
+            assert_eq!(span("\\u{1F600}", "hi \u{1F600}", use_dfs), Some((3, 7)));
+            assert_eq!(span("[\\t\\n]+", "a\t\nb", use_dfs), Some((1, 3)));
+            assert_eq!(span("[\\x00-\\x1F]", "ab\x1Bc", use_dfs), Some((2, 3)));
+            assert_eq!(span("[^\\u{0}-\\x7F]", "abc\u{e9}", use_dfs), Some((3, 5)));
+            assert_eq!(span("\\n", "no newline", use_dfs), None);
+        }
+
+        assert_eq!(parse_error("\\x4"), ParseError::InvalidCodePoint(1));
+        assert_eq!(parse_error("a\\xZ1"), ParseError::InvalidCodePoint(2));
+        assert_eq!(parse_error("\\u12"), ParseError::InvalidCodePoint(1));
+        assert_eq!(parse_error("\\u{}"), ParseError::InvalidCodePoint(1));
+        assert_eq!(parse_error("\\u{41"), ParseError::InvalidCodePoint(1));
+        assert_eq!(
+            parse_error("\\u{123456789}"),
+            ParseError::InvalidCodePoint(1)
+        );
+        assert_eq!(parse_error("[\\xG0]"), ParseError::InvalidCodePoint(2));
+        assert_eq!(
+            parse_error("ab\\u{110000}"),
+            ParseError::CodePointOutOfRange(3, 0x110000)
+        );
+        assert_eq!(
+            parse_error("\\x{D800}"),
+            ParseError::CodePointOutOfRange(1, 0xD800)
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("a\\|b", "xa|b", use_dfs), Some((1, 4)));
+            assert_eq!(span("a\\|b", "a", use_dfs), None);
+            assert_eq!(span("[^\\|]+", "||foo|bar", use_dfs), Some((2, 5)));
+            assert_eq!(span("[\\.\\*]+", "a.*b", use_dfs), Some((1, 3)));
+            assert_eq!(span("\\-", "a-b", use_dfs), Some((1, 2)));
+
+            for text in ["a|b", "1+1=2", "(x)[y]{z}", "^$.*?\\", "[a-z]", "é|ü"] {
+                let expr = format!("^{}$", escape(text));
+                assert_eq!(span(&expr, text, use_dfs), Some((0, text.len())));
+            }
+            assert_eq!(span(&escape("a.c"), "abc", use_dfs), None);
+
+            // escaped white spaces match themselves under (?x)
+            for text in ["a b", "a\tb # c", " \n "] {
+                let re = RegexBuilder::new(&format!("^{}$", escape(text)))
+                    .use_dfs(use_dfs)
+                    .ignore_whitespace(true)
+                    .build()
+                    .unwrap();
+                assert!(re.is_match(text).unwrap());
+            }
+            assert_eq!(span("(?x)[\\ \\\t]+", "a \t b", use_dfs), Some((1, 4)));
+        }
+
+        // dangling backslash
+        assert_eq!(parse_error("abc\\"), ParseError::InvalidEscape(3, '\\'));
+        assert_eq!(parse_error("(a)\\"), ParseError::InvalidEscape(3, '\\'));
+
+        assert_eq!(escape(""), "");
+        assert_eq!(escape("abc"), "abc");
+        assert_eq!(escape("a|b"), "a\\|b");
+        assert_eq!(escape("a b\t"), "a\\ b\\\t");
+        assert_eq!(
+            escape("\\.+*?()|[]{}^$-"),
+            "\\\\\\.\\+\\*\\?\\(\\)\\|\\[\\]\\{\\}\\^\\$\\-"
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        for use_dfs in [true, false] {
+            // (?i)
+            assert_eq!(span("(?i)error", "an ERROR here", use_dfs), Some((3, 8)));
+            assert_eq!(span("(?i)error", "Error", use_dfs), Some((0, 5)));
+            assert_eq!(span("error", "ERROR", use_dfs), None);
+            assert_eq!(span("(?i)[a-c]+", "xAbCd", use_dfs), Some((1, 4)));
+            assert_eq!(span("(?i)[^a]", "aAb", use_dfs), Some((2, 3)));
+            assert_eq!(span("(?i)\\x41", "a", use_dfs), Some((0, 1)));
+            assert_eq!(
+                span("(?i)straße", "STRASSE straSSe STRAßE", use_dfs),
+                Some((16, 23))
+            );
+            assert_eq!(span("(?i)σ", "Σ", use_dfs), Some((0, 2)));
+
+            // scoped flags
+            assert_eq!(span("a(?i:b)c", "aBc", use_dfs), Some((0, 3)));
+            assert_eq!(span("a(?i:b)c", "aBC", use_dfs), None);
+            assert_eq!(span("a(?i)b|c", "C", use_dfs), Some((0, 1)));
+            assert_eq!(span("(a(?i)b)c", "aBc", use_dfs), Some((0, 3)));
+            assert_eq!(span("(a(?i)b)c", "aBC", use_dfs), None);
+            assert_eq!(span("(?i)a(?-i)b", "Ab", use_dfs), Some((0, 2)));
+            assert_eq!(span("(?i)a(?-i)b", "AB", use_dfs), None);
+            assert_eq!(span("(?i)a(?-i:b)c", "AbC", use_dfs), Some((0, 3)));
+
+            // (?m) and (?s)
+            assert_eq!(span("(?m)^b$", "a\nb\nc", use_dfs), Some((2, 3)));
+            assert_eq!(span("^b$", "a\nb\nc", use_dfs), None);
+            assert_eq!(span("a.b", "a\nb", use_dfs), None);
+            assert_eq!(span("(?s)a.b", "a\nb", use_dfs), Some((0, 3)));
+            assert_eq!(span("(?s:a.)b|a.c", "a\nc", use_dfs), None);
+            assert_eq!(span("(?ms)^a.b", "x\na\nb", use_dfs), Some((2, 5)));
+
+            // (?x)
+            assert_eq!(span("(?x) a b c ", "abc", use_dfs), Some((0, 3)));
+            assert_eq!(span("(?x) a+ # some a\n b", "aab", use_dfs), Some((0, 3)));
+            assert_eq!(span("(?x) a\\ b [ ]c \\#", "a b c#", use_dfs), Some((0, 6)));
+            assert_eq!(span("(?x: a b ) c", "ab c", use_dfs), Some((0, 4)));
+            assert_eq!(span("(?x) a\\\tb [\\\n]", "a\tb\n", use_dfs), Some((0, 4)));
+        }
+
+        // builder options
+        let re = RegexBuilder::new("error")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("ERROR").unwrap());
+        let re = RegexBuilder::new("e(?-i)rror")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("Error").unwrap());
+        assert!(!re.is_match("ERROR").unwrap());
+        let re = RegexBuilder::new("a b # comment")
+            .ignore_whitespace(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("ab").unwrap());
+        assert!(!re.is_match("a b").unwrap());
+
+        assert_eq!(parse_error("(?)a"), ParseError::UnknownGroup(2));
+        assert_eq!(parse_error("(?q)a"), ParseError::UnknownGroup(2));
+        assert_eq!(parse_error("(?iq)a"), ParseError::InvalidFlag(3));
+        assert_eq!(parse_error("(?i-)a"), ParseError::InvalidFlag(4));
+        assert_eq!(parse_error("(?-:a)"), ParseError::InvalidFlag(3));
+        assert_eq!(parse_error("(?i-m-s)a"), ParseError::InvalidFlag(5));
+        assert_eq!(parse_error("(?i"), ParseError::UnknownGroup(1));
+        assert_eq!(parse_error("a(?i)*"), ParseError::NoPrev(5));
+        assert_eq!(parse_error("(?s)+"), ParseError::NoPrev(4));
+        assert_eq!(parse_error("a(?-m){2}"), ParseError::NoPrev(6));
+        assert_eq!(parse_error("a(?x) *"), ParseError::NoPrev(6));
+        assert_eq!(parse_error("(?x)a(?i) *"), ParseError::NoPrev(10));
+        assert_eq!(parse_error("(?x)a(?i) # c\n +"), ParseError::NoPrev(15));
+        assert_eq!(span("a(?-x) *", "a  ", true), Some((0, 3)));
+    }
+
+    #[test]
+    fn test_case_folding() {
+        for use_dfs in [true, false] {
+            for line in ["error", "ERROR", "Error", "eRrOr"] {
+                assert_eq!(fold("error", line, use_dfs), Some((0, 5)));
+            }
+            assert_eq!(fold("ERROR", "an error", use_dfs), Some((3, 8)));
+
+            // chars folded beyond ASCII
+            assert_eq!(fold("k", "\u{212A}", use_dfs), Some((0, 3))); // Kelvin sign
+            assert_eq!(fold("\u{212A}", "k", use_dfs), Some((0, 1)));
+            assert_eq!(fold("s", "ſ", use_dfs), Some((0, 2))); // long s
+            assert_eq!(fold("ſ", "S", use_dfs), Some((0, 1)));
+            assert_eq!(fold("ω", "Ω", use_dfs), Some((0, 2)));
+            assert_eq!(fold("Ω", "\u{2126}", use_dfs), Some((0, 3))); // Ohm sign
+            assert_eq!(fold("σ", "ς", use_dfs), Some((0, 2))); // final sigma
+            assert_eq!(fold("ǆ", "ǅ", use_dfs), Some((0, 2))); // title case
+            assert_eq!(fold("ǆ", "Ǆ", use_dfs), Some((0, 2)));
+            assert_eq!(fold("привет", "ПРИВЕТ", use_dfs), Some((0, 12)));
+            assert_eq!(fold("ß", "ẞ", use_dfs), Some((0, 3)));
+            assert_eq!(fold("ß", "ss", use_dfs), None); // not a simple case folding
+
+            // classes
+            assert_eq!(fold("[a-z]+", "ABC\u{212A}", use_dfs), Some((0, 6)));
+            assert_eq!(fold("[^k]", "K\u{212A}kx", use_dfs), Some((5, 6)));
+            assert_eq!(fold("[α-ω]+", "ΑΒΓ", use_dfs), Some((0, 6)));
+            assert_eq!(fold("1", "1", use_dfs), Some((0, 1)));
+
+            // each element of a class is folded before negation and set operations
+            assert_eq!(fold("[[:upper:]]+", "abC", use_dfs), Some((0, 3)));
+            assert_eq!(fold("[a-z--k]+", "jkl", use_dfs), Some((0, 1)));
+            for line in ["xkK_", "\u{212A}", "a-b"] {
+                assert_eq!(
+                    ascii("(?i)\\W", line, use_dfs),
+                    ascii("(?i)[\\W]", line, use_dfs)
+                );
+            }
+            assert_eq!(ascii("(?i)[\\W]+", "xkK\u{212A}_", use_dfs), None);
+            assert_eq!(ascii("(?i)[^\\w]+", "xkK\u{212A}_", use_dfs), None);
+            assert_eq!(ascii("(?i)[[:^word:]]+", "xkK\u{212A}_", use_dfs), None);
+        }
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("\\bcat\\b", "concat cat cats", use_dfs), Some((7, 10)));
+            assert_eq!(span("\\bcat\\b", "concatenate", use_dfs), None);
+            assert_eq!(span("\\bcat\\b", "cat", use_dfs), Some((0, 3)));
+            assert_eq!(span("\\Bcat\\B", "cat concatenate", use_dfs), Some((7, 10)));
+            assert_eq!(span("\\Bcat", "cat", use_dfs), None);
+            assert_eq!(span("\\b\\w+\\b", "  foo_bar1 ", use_dfs), Some((2, 10)));
+            assert_eq!(span("\\b", "", use_dfs), None);
+            assert_eq!(span("\\B", "", use_dfs), Some((0, 0)));
+            assert_eq!(span("\\b", "  ", use_dfs), None);
+            assert_eq!(span("a\\b.", "ab a-", use_dfs), Some((3, 5)));
+            assert_eq!(
+                span("(?i)\\berror\\b", "Errors: ERROR!", use_dfs),
+                Some((8, 13))
+            );
+
+            // Unicode word chars by default
+            assert_eq!(span("\\bé", "café é", use_dfs), Some((6, 8)));
+            assert_eq!(span("\\bпривет\\b", "(привет)", use_dfs), Some((1, 13)));
+
+            assert_eq!(ascii("\\bé", "café é", use_dfs), Some((3, 5)));
+            assert_eq!(ascii("\\bcat\\b", "écat", use_dfs), Some((2, 5)));
+            assert_eq!(ascii("é\\B", "é ", use_dfs), Some((0, 2)));
+        }
+    }
+
+    #[test]
+    fn test_lookaround() {
+        // lookahead
+        assert_eq!(span("foo(?=bar)", "foobaz foobar", true), Some((7, 10)));
+        assert_eq!(span("foo(?!bar)", "foobar foobaz", true), Some((7, 10)));
+        assert_eq!(span("\\b(?!un)\\w+", "undo redo", true), Some((5, 9)));
+        assert_eq!(span("(?=\\d{3}$)\\d+", "12345", true), Some((2, 5)));
+        assert_eq!(span("(?=a)b", "ab", true), None);
+        assert_eq!(span("a(?=)b", "ab", true), Some((0, 2)));
+        assert_eq!(span("a(?!)", "ab", true), None);
+        assert_eq!(
+            span("(?=.*\\d)(?=.*[a-z])\\w{6,}", "abc123", true),
+            Some((0, 6))
+        );
+        assert_eq!(span("(?=.*\\d)(?=.*[a-z])\\w{6,}", "abcdef", true), None);
+
+        // lookbehind
+        assert_eq!(span("(?<=\\$)\\d+", "cost: $42", true), Some((7, 9)));
+        assert_eq!(span("(?<!\\$)\\b\\d+", "$42 17", true), Some((4, 6)));
+        assert_eq!(span("(?<=ab|c)d", "abd", true), Some((2, 3)));
+        assert_eq!(span("(?<=ab|c)d", "cd", true), Some((1, 2)));
+        assert_eq!(span("(?<=ab|c)d", "bd", true), None);
+        assert_eq!(span("(?<=a{2,3})b", "aab", true), Some((2, 3)));
+        assert_eq!(span("(?<=^|,)x", "y,x", true), Some((2, 3)));
+        assert_eq!(span("(?<=é)b", "éb", true), Some((2, 3)));
+        assert_eq!(span("(?<=a)", "a", true), Some((1, 1)));
+
+        // captures
+        let re = Regex::new("(?=(\\w+))\\w").unwrap();
+        let caps = re.captures("  abc").unwrap().unwrap();
+        assert_eq!(&caps[0], "a");
+        assert_eq!(&caps[1], "abc");
+        let re = Regex::new("(?!(a)b)\\w").unwrap();
+        let caps = re.captures("abac").unwrap().unwrap();
+        assert_eq!(caps.get(0).map(|m| m.range()), Some(1..2));
+        assert!(caps.get(1).is_none());
+        let re = Regex::new("(?=(a))ab|ac").unwrap();
+        let caps = re.captures("ac").unwrap().unwrap();
+        assert_eq!(&caps[0], "ac");
+        assert!(caps.get(1).is_none());
+
+        // width-first search cannot run lookaround
+        for expr in ["a(?=b)", "a(?!b)", "(?<=a)b", "(?<!a)b"] {
+            let err = RegexBuilder::new(expr).use_dfs(false).build().unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<CodeGenError>(),
+                Some(&CodeGenError::Unsupported("lookaround"))
+            );
+        }
+
+        assert_eq!(parse_error("a(?<=b+)"), ParseError::UnboundedLookBehind(1));
+        assert_eq!(
+            parse_error("(?<!a|b*)c"),
+            ParseError::UnboundedLookBehind(0)
+        );
+        assert_eq!(
+            parse_error("(?<=a{2,})c"),
+            ParseError::UnboundedLookBehind(0)
+        );
+        assert_eq!(parse_error("(?<x"), ParseError::InvalidGroupName(2));
+    }
+
+    #[test]
+    fn test_backref() {
+        assert_eq!(
+            span("\\b(\\w+) \\1\\b", "this is is a test", true),
+            Some((5, 10))
+        );
+        assert_eq!(span("\\b(\\w+) \\1\\b", "the theory", true), None);
+        assert_eq!(span("(a|b)\\1", "abba", true), Some((1, 3)));
+
+        // a group in a loop refers to its last completed iteration
+        assert_eq!(span("(?:(a|b\\1)c)+", "acbc", true), Some((0, 2)));
+        assert_eq!(span("(?:(a|b\\1)c)+", "acbac", true), Some((0, 5)));
+        assert_eq!(span("(?:(a|\\1b))+", "ab", true), Some((0, 1)));
+        assert_eq!(
+            span("(?<q>['\"]).*?\\k<q>", "say \"it's\" ok", true),
+            Some((4, 10))
+        );
+        assert_eq!(span("(?P<x>a+)-\\k<x>", "aaa-aa", true), Some((1, 6)));
+        assert_eq!(span("(a)?b\\1", "b", true), None);
+        assert_eq!(span("(a*)b\\1", "b", true), Some((0, 1)));
+        assert_eq!(
+            span("(a)(b)(c)(d)(e)(f)(g)(h)(i)(j)\\10", "abcdefghijj", true),
+            Some((0, 11))
+        );
+        assert_eq!(span("(é)\\1", "éé", true), Some((0, 4)));
+        assert_eq!(span("(a)\\1", "aA", true), None);
+        assert_eq!(span("(?i)(a)\\1", "aA", true), Some((0, 2)));
+        assert_eq!(span("(?i)(k)\\1", "k\u{212A}", true), Some((0, 4)));
+
+        // width-first search cannot run backreferences
+        let err = RegexBuilder::new("(a)\\1")
+            .use_dfs(false)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<CodeGenError>(),
+            Some(&CodeGenError::Unsupported("backreference"))
+        );
+
+        assert_eq!(parse_error("(a)\\2"), ParseError::InvalidBackref(4));
+        assert_eq!(parse_error("\\1(a)"), ParseError::InvalidBackref(1));
+        assert_eq!(parse_error("(?<a>x)\\k<b>"), ParseError::InvalidBackref(8));
+        assert_eq!(parse_error("(a)\\k"), ParseError::InvalidBackref(4));
+        assert_eq!(parse_error("(a)\\k<"), ParseError::InvalidGroupName(5));
+        assert_eq!(parse_error("\\0"), ParseError::InvalidEscape(1, '0'));
+    }
+
+    #[test]
+    fn test_atomic() {
+        // atomic groups
+        assert_eq!(span("(?>a+)b", "aaab", true), Some((0, 4)));
+        assert_eq!(span("(?>a+)a", "aaaa", true), None);
+        assert_eq!(span("(?>a|ab)c", "abc", true), None);
+        assert_eq!(span("(?:a|ab)c", "abc", true), Some((0, 3)));
+        assert_eq!(span("(?>ab|a)c", "abc", true), Some((0, 3)));
+        assert_eq!(span("x(?>a*?)b", "xaab", true), None);
+        assert_eq!(span("(?>)a", "a", true), Some((0, 1)));
+        assert_eq!(span("(?>(?>a+)b|a)c", "aac", true), Some((1, 3)));
+        assert_eq!(span("(?>a*)*b", "aab", true), Some((0, 3)));
+
+        // possessive quantifiers
+        assert_eq!(span("a*+b", "aaab", true), Some((0, 4)));
+        assert_eq!(span("a*+a", "aaaa", true), None);
+        assert_eq!(span("a++a", "aaaa", true), None);
+        assert_eq!(span("a?+a", "a", true), None);
+        assert_eq!(span("a?+a", "aa", true), Some((0, 2)));
+        assert_eq!(span("a{1,3}+a", "aaa", true), None);
+        assert_eq!(span("a{1,3}+a", "aaaa", true), Some((0, 4)));
+        assert_eq!(span("\"[^\"]*+\"", "say \"hi\"", true), Some((4, 8)));
+        assert_eq!(span("(ab)++c", "ababc", true), Some((0, 5)));
+        assert_eq!(span("a+?+", "aa", true), Some((0, 2))); // (a+?)+, not possessive
+
+        // captures
+        let re = Regex::new("(?>(a+)|(b))c").unwrap();
+        let caps = re.captures("bc").unwrap().unwrap();
+        assert!(caps.get(1).is_none());
+        assert_eq!(&caps[2], "b");
+        let re = Regex::new("(?>(a))b|ac").unwrap();
+        let caps = re.captures("ac").unwrap().unwrap();
+        assert_eq!(&caps[0], "ac");
+        assert!(caps.get(1).is_none());
+
+        // width-first search cannot run atomic groups
+        for expr in ["(?>a+)b", "a*+b", "a++", "a?+", "a{2,}+"] {
+            let err = RegexBuilder::new(expr).use_dfs(false).build().unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<CodeGenError>(),
+                Some(&CodeGenError::Unsupported("atomic group"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_property() {
+        for use_dfs in [true, false] {
+            // general categories
+            assert_eq!(span("\\p{L}+", "123 héllo", use_dfs), Some((4, 10)));
+            assert_eq!(span("\\pL+", "123 héllo", use_dfs), Some((4, 10)));
+            assert_eq!(span("\\p{Letter}+", "123 héllo", use_dfs), Some((4, 10)));
+            assert_eq!(span("\\p{Lu}", "abcDef", use_dfs), Some((3, 4)));
+            assert_eq!(
+                span("\\p{Uppercase_Letter}", "abcDef", use_dfs),
+                Some((3, 4))
+            );
+            assert_eq!(
+                span("\\p{uppercase letter}", "abcDef", use_dfs),
+                Some((3, 4))
+            );
+            assert_eq!(span("\\p{gc=Nd}+", "x٣4", use_dfs), Some((1, 4)));
+            assert_eq!(span("\\p{Sc}", "price: €5", use_dfs), Some((7, 10)));
+            assert_eq!(span("\\P{L}+", "ab12cd", use_dfs), Some((2, 4)));
+
+            // scripts
+            assert_eq!(span("\\p{Greek}+", "alpha αβγ", use_dfs), Some((6, 12)));
+            assert_eq!(span("\\p{Grek}+", "alpha αβγ", use_dfs), Some((6, 12)));
+            assert_eq!(
+                span("\\p{Script=Cyrillic}+", "hi привет", use_dfs),
+                Some((3, 15))
+            );
+            assert_eq!(span("\\p{Han}+", "日本語です", use_dfs), Some((0, 9)));
+            assert_eq!(span("\\p{Hiragana}+", "日本語です", use_dfs), Some((9, 15)));
+            assert_eq!(span("\\P{Greek}+", "αβc1γ", use_dfs), Some((4, 6)));
+
+            // in character classes
+            assert_eq!(span("[\\p{Greek}\\d]+", "x1α2", use_dfs), Some((1, 5)));
+            assert_eq!(span("[^\\p{L}\\s]+", "ab, cd", use_dfs), Some((2, 3)));
+            assert_eq!(span("[\\P{L}]", "ab1", use_dfs), Some((2, 3)));
+            assert_eq!(span("(?i)\\p{Lu}", "abc", use_dfs), Some((0, 1)));
+        }
+
+        assert_eq!(
+            parse_error("a\\p{Foo}"),
+            ParseError::UnknownProperty(2, "Foo".to_string())
+        );
+        assert_eq!(
+            parse_error("[\\P{gc=Greek}]"),
+            ParseError::UnknownProperty(2, "gc=Greek".to_string())
+        );
+        assert_eq!(
+            parse_error("\\pX"),
+            ParseError::UnknownProperty(1, "X".to_string())
+        );
+        assert_eq!(parse_error("\\p{Greek"), ParseError::InvalidEscape(1, 'p'));
+        assert_eq!(parse_error("\\p"), ParseError::InvalidEscape(1, 'p'));
+    }
+
+    #[test]
+    fn test_posix_class() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("[[:alpha:]]+", "12abC3", use_dfs), Some((2, 5)));
+            assert_eq!(span("[[:digit:]]+", "ab123c", use_dfs), Some((2, 5)));
+            assert_eq!(span("[[:space:]]+", "a \t\nb", use_dfs), Some((1, 4)));
+            assert_eq!(span("[[:punct:]]+", "ab,.!c", use_dfs), Some((2, 5)));
+            assert_eq!(
+                span("[[:upper:][:digit:]]+", "abC1Dx", use_dfs),
+                Some((2, 5))
+            );
+            assert_eq!(span("[[:xdigit:]]+", "xyzBEEFg", use_dfs), Some((3, 7)));
+            assert_eq!(span("[[:alnum:]_]+", "--a_1--", use_dfs), Some((2, 5)));
+            assert_eq!(span("[[:blank:]]", "a\nb c", use_dfs), Some((3, 4)));
+            assert_eq!(span("[[:cntrl:]]", "ab\x7F", use_dfs), Some((2, 3)));
+            assert_eq!(span("[[:lower:]]", "ABé c", use_dfs), Some((5, 6)));
+            assert_eq!(span("[[:alpha:]]", "é", use_dfs), None);
+
+            // negation
+            assert_eq!(span("[^[:space:]]+", "  foo bar", use_dfs), Some((2, 5)));
+            assert_eq!(span("[[:^digit:]]+", "12ab3", use_dfs), Some((2, 4)));
+            assert_eq!(span("[^[:^digit:]]+", "ab12c", use_dfs), Some((2, 4)));
+
+            // not a POSIX class but a nested class
+            assert_eq!(span("[[:a]+]+", ":a+[", use_dfs), Some((0, 3)));
+            assert_eq!(span("[\\[]", "a[", use_dfs), Some((1, 2)));
+        }
+
+        assert_eq!(
+            parse_error("[[:foo:]]"),
+            ParseError::UnknownPosixClass(1, "foo".to_string())
+        );
+        assert_eq!(
+            parse_error("a[b[:^Alpha:]]"),
+            ParseError::UnknownPosixClass(3, "Alpha".to_string())
+        );
+        assert_eq!(parse_error("[[:alpha:]"), ParseError::NoRightBracket(0));
+    }
+
+    #[test]
+    fn test_class_set() {
+        for use_dfs in [true, false] {
+            // nesting
+            assert_eq!(span("[a[bc]]+", "xabcx", use_dfs), Some((1, 4)));
+            assert_eq!(span("[[a-c][x-z]]+", "dayz", use_dfs), Some((1, 4)));
+            assert_eq!(span("[^[a-c]]+", "abdec", use_dfs), Some((2, 4)));
+            assert_eq!(span("[[^a-c]]+", "abdec", use_dfs), Some((2, 4)));
+            assert_eq!(span("[a[^a]]", "a", use_dfs), Some((0, 1)));
+
+            // intersection
+            assert_eq!(span("[a-z&&[^x]]+", "xaybx", use_dfs), Some((1, 4)));
+            assert_eq!(span("[a-z&&[^aeiou]]+", "aebcdi", use_dfs), Some((2, 5)));
+            assert_eq!(span("[\\w&&\\d]+", "ab12c", use_dfs), Some((2, 4)));
+            assert_eq!(span("[a-m&&h-z&&j-k]+", "hijkl", use_dfs), Some((2, 4)));
+            assert_eq!(span("[a&&b]", "ab", use_dfs), None);
+            assert_eq!(span("[a&b]+", "a&b", use_dfs), Some((0, 3)));
+            assert_eq!(span("[a\\&\\&b]+", "a&&b", use_dfs), Some((0, 4)));
+            assert_eq!(
+                span(&format!("[{}]+", escape("a&&b")), "a&&b", use_dfs),
+                Some((0, 4))
+            );
+
+            // difference
+            assert_eq!(span("[\\p{L}--[aeiou]]+", "aebcdi", use_dfs), Some((2, 5)));
+            assert_eq!(span("[a-z--aeiou]+", "aebcdi", use_dfs), Some((2, 5)));
+            assert_eq!(span("[\\d--5]+", "55123567", use_dfs), Some((2, 5)));
+            assert_eq!(span("[a-z--[b-y]--z]", "zyb a", use_dfs), Some((4, 5)));
+            assert_eq!(span("[-a]+", "x-a", use_dfs), Some((1, 3)));
+            assert_eq!(span("[a-]+", "x-a", use_dfs), Some((1, 3)));
+
+            // left to right, after union
+            assert_eq!(span("[a-z--b&&a-c]+", "bdac", use_dfs), Some((2, 4)));
+            assert_eq!(span("[ab&&bc]", "ab", use_dfs), Some((1, 2)));
+
+            // negation of the whole class, case folding
+            assert_eq!(span("[^a-z&&[^x]]+", "axXb", use_dfs), Some((1, 3)));
+            assert_eq!(span("(?i)[a-z--[aeiou]]+", "AEbCdI", use_dfs), Some((2, 5)));
+        }
+
+        assert_eq!(parse_error("[a&&]"), ParseError::EmptyClass(0));
+        assert_eq!(parse_error("x[--a]"), ParseError::EmptyClass(1));
+        assert_eq!(parse_error("[a[]]"), ParseError::EmptyClass(2));
+        assert_eq!(parse_error("[a[b]"), ParseError::NoRightBracket(0));
+    }
+
+    #[test]
+    fn test_empty_alternative() {
+        for use_dfs in [true, false] {
+            assert_eq!(span("", "abc", use_dfs), Some((0, 0)));
+            assert_eq!(span("a|", "xa", use_dfs), Some((0, 0)));
+            assert_eq!(span("a|", "ax", use_dfs), Some((0, 1)));
+            assert_eq!(span("|b", "bbb", use_dfs), Some((0, 0)));
+            assert_eq!(span("a||b", "b", use_dfs), Some((0, 0)));
+            assert_eq!(span("a||b", "a", use_dfs), Some((0, 1)));
+            assert_eq!(span("x(|b)y", "xy xby", use_dfs), Some((0, 2)));
+            assert_eq!(span("x(|b)y", "xby", use_dfs), Some((0, 3)));
+            assert_eq!(span("x(b|)y", "xby", use_dfs), Some((0, 3)));
+            assert_eq!(span("x()y", "xy", use_dfs), Some((0, 2)));
+            assert_eq!(span("x(?:)y", "xy", use_dfs), Some((0, 2)));
+            assert_eq!(span("x()*y", "xy", use_dfs), Some((0, 2)));
+            assert_eq!(span("x(|)+y", "xy", use_dfs), Some((0, 2)));
+            assert_eq!(span("x(a|)*y", "xaay", use_dfs), Some((0, 4)));
+            assert_eq!(span("x(|a)*y", "xaay", use_dfs), Some((0, 4)));
+            assert_eq!(span("x(|a)*?y", "xaay", use_dfs), Some((0, 4)));
+            assert_eq!(span("^(a|)$", "", use_dfs), Some((0, 0)));
+            assert_eq!(span("(|)", "", use_dfs), Some((0, 0)));
+
+            // leftmost-first priority as in the regex crate:
+            // an empty first iteration ends the loop, a later one falls back to other alternatives
+            assert_eq!(span("(?:\\d?|a{1,2})*", "1a1", use_dfs), Some((0, 3)));
+            assert_eq!(span("(|a)*", "aa", use_dfs), Some((0, 0)));
+            assert_eq!(span("(a??)*", "aa", use_dfs), Some((0, 0)));
+            assert_eq!(span("(|a)*?", "aa", use_dfs), Some((0, 0)));
+            assert_eq!(span("(|a){2,}", "aa", use_dfs), Some((0, 0)));
+            assert_eq!(span("(a|)*", "aa", use_dfs), Some((0, 2)));
+            for (expr, group) in [("(|a)*", 0..0), ("(a??)*", 0..0), ("(a|)*", 1..2)] {
+                let re = RegexBuilder::new(expr).use_dfs(use_dfs).build().unwrap();
+                let caps = re.captures("aa").unwrap().unwrap();
+                assert_eq!(caps.get(1).map(|m| m.range()), Some(group));
+            }
+
+            let re = RegexBuilder::new("x(|b)(c|)()y")
+                .use_dfs(use_dfs)
+                .build()
+                .unwrap();
+            assert_eq!(re.captures_len(), 4);
+            let caps = re.captures("xy").unwrap().unwrap();
+            assert_eq!(caps.get(1).map(|m| m.range()), Some(1..1));
+            assert_eq!(caps.get(2).map(|m| m.range()), Some(1..1));
+            assert_eq!(caps.get(3).map(|m| m.range()), Some(1..1));
+
+            let full = RegexBuilder::new("a|")
+                .use_dfs(use_dfs)
+                .match_kind(MatchKind::Full)
+                .build()
+                .unwrap();
+            assert!(full.is_match("").unwrap());
+            assert!(full.is_match("a").unwrap());
+            assert!(!full.is_match("b").unwrap());
+        }
+
+        assert!(do_matching("|b", "bbb", true).unwrap());
+
+        // repeating an empty group generates no loop
+        for expr in [
+            "(?:){99999999999}",
+            "(?:){99999999999,}",
+            "(?:){0,99999999999}",
+            "(?i:){99999999999}",
+            "(?:(?:){99999}){99999}",
+            "(?:a{0}){99999999999}",
+        ] {
+            let re = Regex::new(expr).unwrap();
+            assert_eq!(re.find("a").unwrap().map(|m| m.range()), Some(0..0));
+        }
+
+        // the body of each repetition is generated once, even if nested
+        let nested = format!("{}a{}", "(?:".repeat(100), "){0,1}".repeat(100));
+        assert_eq!(span(&nested, "a", true), Some((0, 1)));
+        let nested = format!("{}{}", "(?:".repeat(100), "){0,1}".repeat(100));
+        assert_eq!(span(&nested, "a", true), Some((0, 0)));
+    }
 }